@@ -1,84 +1,290 @@
-use std::collections::{BTreeSet, BinaryHeap};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
 use std::fmt;
 
 use bumpalo::Bump;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
+use iced_x86::Mnemonic;
 
+use crate::graph::EdgeKind;
 use crate::heuristics::EdgeDistanceHeuristic;
-use crate::match_star::{match_star, MatchContext};
+use crate::match_star::{match_edges, match_star, MatchConfig, MatchContext, StarMatch};
 use crate::object::CodeMetadata;
 
-/// Performs call graph matching with the specified partial matching and heuristics.
+/// Performs call graph matching with the specified partial matching and heuristics. The costs
+/// of matching functions are computed as set by the config.
 /// The algorithm is based on
 /// [Error-tolerant graph matching in linear computational cost using an initial small partial matching](https://www.sciencedirect.com/science/article/abs/pii/S0167865518301235).
 pub fn belief_prop(
     lhs: &CodeMetadata,
     rhs: &CodeMetadata,
     seeds: impl IntoIterator<Item = (u64, u64)>,
-    heuristics: &impl EdgeDistanceHeuristic,
+    heuristics: &(impl EdgeDistanceHeuristic + ?Sized),
+    config: &MatchConfig,
 ) -> Mapping {
-    let mut bump = Bump::new();
-
-    let mut pending = BinaryHeap::new();
-    let mut matching = BTreeSet::new();
-    let mut matching_rhs = HashSet::new();
-    let mut computed = HashSet::new();
-    let ctx = MatchContext::new(lhs, rhs);
+    let mut propagation = Propagation::new(lhs, rhs, heuristics, config);
+    for pair in seeds {
+        propagation.seed(pair, Origin::Seed);
+    }
+    propagation.run();
+    propagation.into_mapping()
+}
 
+/// Performs call graph matching like [`belief_prop`], but once the propagation stops, the
+/// functions left unmatched are paired up by their signatures and used as new seeds. This is
+/// repeated until no new pairs are found. A signature is made of the opcodes of a function and
+/// its callees mapped through the matched pairs, and only signatures that are unique on both
+/// sides are paired.
+pub fn belief_prop_with_rematch(
+    lhs: &CodeMetadata,
+    rhs: &CodeMetadata,
+    seeds: impl IntoIterator<Item = (u64, u64)>,
+    heuristics: &(impl EdgeDistanceHeuristic + ?Sized),
+    config: &MatchConfig,
+) -> Mapping {
+    let mut propagation = Propagation::new(lhs, rhs, heuristics, config);
     for pair in seeds {
-        let star0 = lhs.call_graph().get_star(pair.0);
-        let star1 = rhs.call_graph().get_star(pair.1);
-        let (dist, map) = match_star(star0, star1, heuristics, ctx, &bump);
-        bump.reset();
-        computed.insert(pair);
-        pending.push(PendingItem::new(pair, dist, map));
-    }
-
-    while let Some(item) = pending.pop() {
-        matching.insert(item.pair);
-        matching_rhs.insert(item.pair.1);
-        pending.retain(|i| i.pair != item.pair && i.pair != (item.pair.1, item.pair.0));
-
-        for &mapping in &item.mappings {
-            if !computed.contains(&mapping)
-                && !matching
-                    .range((mapping.0, u64::MIN)..=(mapping.0, u64::MAX))
-                    .any(|_| true)
-                && !matching_rhs.contains(&mapping.1)
+        propagation.seed(pair, Origin::Seed);
+    }
+    loop {
+        propagation.run();
+        let leftovers = propagation.leftover_pairs();
+        if leftovers.is_empty() {
+            break;
+        }
+        for pair in leftovers {
+            propagation.seed(pair, Origin::Rematch);
+        }
+    }
+    propagation.into_mapping()
+}
+
+/// The state of the propagation of matches through two call graphs.
+struct Propagation<'a, H: ?Sized> {
+    lhs: &'a CodeMetadata,
+    rhs: &'a CodeMetadata,
+    heuristics: &'a H,
+    config: &'a MatchConfig,
+    bump: Bump,
+    pending: BinaryHeap<PendingItem>,
+    matching: BTreeSet<(u64, u64)>,
+    details: BTreeMap<(u64, u64), MatchDetails>,
+    tentative: Vec<((u64, u64), MatchDetails)>,
+    matching_rhs: HashSet<u64>,
+    computed: HashSet<(u64, u64)>,
+}
+
+impl<'a, H: EdgeDistanceHeuristic + ?Sized> Propagation<'a, H> {
+    fn new(lhs: &'a CodeMetadata, rhs: &'a CodeMetadata, heuristics: &'a H, config: &'a MatchConfig) -> Self {
+        Self {
+            lhs,
+            rhs,
+            heuristics,
+            config,
+            bump: Bump::new(),
+            pending: BinaryHeap::new(),
+            matching: BTreeSet::new(),
+            details: BTreeMap::new(),
+            tentative: vec![],
+            matching_rhs: HashSet::new(),
+            computed: HashSet::new(),
+        }
+    }
+
+    fn seed(&mut self, pair: (u64, u64), origin: Origin) {
+        let star = self.match_star(pair);
+        self.computed.insert(pair);
+        self.pending.push(PendingItem::new(pair, origin, star));
+    }
+
+    fn run(&mut self) {
+        let ctx = MatchContext::new(self.lhs, self.rhs);
+
+        while let Some(item) = self.pending.pop() {
+            // a function is matched at most once, the pair with the lowest distance wins
+            if self.is_matched_lhs(item.pair.0) || self.matching_rhs.contains(&item.pair.1) {
+                continue;
+            }
+            if !self.is_trusted(&item) {
+                if self.config.keep_tentative {
+                    self.tentative.push((item.pair, item.details()));
+                }
+                continue;
+            }
+            self.matching.insert(item.pair);
+            self.matching_rhs.insert(item.pair.1);
+            self.pending
+                .retain(|i| i.pair != item.pair && i.pair != (item.pair.1, item.pair.0));
+            self.details.insert(item.pair, item.details());
+
+            // The matches spread to the callees through the stars of the pair and to the callers
+            // through the stars made of the incoming edges. Imports are called from all over the
+            // program, so their callers have little in common and aren't matched.
+            let star0 = self.lhs.call_graph().get_reverse_star(item.pair.0);
+            let star1 = self.rhs.call_graph().get_reverse_star(item.pair.1);
+            let callers = if star0.edges_of_kind(EdgeKind::Import).next().is_some()
+                || star1.edges_of_kind(EdgeKind::Import).next().is_some()
+                || star0.edges().all(|&addr| self.is_matched_lhs(addr))
+                || star1.edges().all(|addr| self.matching_rhs.contains(addr))
             {
-                let star0 = lhs.call_graph().get_star(mapping.0);
-                let star1 = rhs.call_graph().get_star(mapping.1);
-                let (dist, candidate_mappings) = match_star(star0, star1, heuristics, ctx, &bump);
-                bump.reset();
+                vec![]
+            } else {
+                let (_, _, callers) = match_edges(star0, star1, self.heuristics, self.config, ctx, &self.bump);
+                self.bump.reset();
+                callers
+            };
+
+            for &mapping in item.star.mappings.iter().chain(&callers) {
+                if !self.computed.contains(&mapping)
+                    && !self.is_matched_lhs(mapping.0)
+                    && !self.matching_rhs.contains(&mapping.1)
+                {
+                    let star = self.match_star(mapping);
+                    self.computed.insert(mapping);
+                    self.pending.push(PendingItem::new(
+                        mapping,
+                        Origin::Parent(item.pair.0, item.pair.1),
+                        star,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Pairs up the unmatched functions with signatures that are unique on both sides. Pairs
+    /// that were already proposed are skipped, because they were rejected before.
+    fn leftover_pairs(&self) -> Vec<(u64, u64)> {
+        let lhs = self.unique_signatures(
+            self.lhs,
+            |addr| !self.is_matched_lhs(addr),
+            |addr| self.matched_rhs(addr),
+        );
+        let rhs = self.unique_signatures(
+            self.rhs,
+            |addr| !self.matching_rhs.contains(&addr),
+            |addr| self.matching_rhs.contains(&addr).then_some(addr),
+        );
+        let mut pairs: Vec<_> = lhs
+            .iter()
+            .filter_map(|(signature, &l)| Some((l?, (*rhs.get(signature)?)?)))
+            .filter(|pair| !self.computed.contains(pair))
+            .collect();
+        pairs.sort_unstable();
+        pairs
+    }
 
-                computed.insert(mapping);
-                pending.push(PendingItem::new(mapping, dist, candidate_mappings));
+    /// Collects the signatures of the unmatched functions. Signatures shared by several
+    /// functions map to `None`. The callees are translated to the addresses of the right-hand
+    /// side functions they're matched with, so that signatures of both sides can be compared.
+    fn unique_signatures<'m>(
+        &self,
+        metadata: &'m CodeMetadata,
+        is_unmatched: impl Fn(u64) -> bool,
+        translate: impl Fn(u64) -> Option<u64>,
+    ) -> HashMap<Signature<'m>, Option<u64>> {
+        let mut signatures = HashMap::new();
+        for addr in metadata.functions().filter(|&addr| is_unmatched(addr)) {
+            let func = metadata.get_function(addr).unwrap();
+            if func.opcodes().is_empty() {
+                continue;
             }
+            let mut callees: Vec<_> = metadata
+                .call_graph()
+                .get_star(addr)
+                .edges()
+                .map(|&callee| translate(callee))
+                .collect();
+            callees.sort_unstable();
+            signatures
+                .entry((func.opcodes(), callees))
+                .and_modify(|unique: &mut Option<u64>| *unique = None)
+                .or_insert(Some(addr));
         }
+        signatures
+    }
+
+    /// Checks whether a pair is below the maximum distance, seeds are trusted regardless.
+    fn is_trusted(&self, item: &PendingItem) -> bool {
+        item.origin == Origin::Seed
+            || self
+                .config
+                .max_distance
+                .is_none_or(|max| item.star.normalized_dist() <= max)
+    }
+
+    fn is_matched_lhs(&self, addr: u64) -> bool {
+        self.matched_rhs(addr).is_some()
+    }
+
+    /// Returns the right-hand side function the left-hand side function is matched with.
+    fn matched_rhs(&self, addr: u64) -> Option<u64> {
+        let &(_, rhs) = self.matching.range((addr, u64::MIN)..=(addr, u64::MAX)).next()?;
+        Some(rhs)
     }
 
-    Mapping { set: matching }
+    fn match_star(&mut self, pair: (u64, u64)) -> StarMatch {
+        let star0 = self.lhs.call_graph().get_star(pair.0);
+        let star1 = self.rhs.call_graph().get_star(pair.1);
+        let ctx = MatchContext::new(self.lhs, self.rhs);
+        let star = match_star(star0, star1, self.heuristics, self.config, ctx, &self.bump);
+        self.bump.reset();
+        star
+    }
+
+    /// Builds the mapping out of the matched pairs. Tentative pairs are kept only when neither
+    /// of their functions was matched, and the first of them to be proposed wins.
+    fn into_mapping(self) -> Mapping {
+        let mut claimed_lhs = HashSet::new();
+        let mut claimed_rhs = HashSet::new();
+        let mut tentative = BTreeMap::new();
+        for ((l, r), details) in &self.tentative {
+            if self.is_matched_lhs(*l)
+                || self.matching_rhs.contains(r)
+                || claimed_lhs.contains(l)
+                || claimed_rhs.contains(r)
+            {
+                continue;
+            }
+            claimed_lhs.insert(*l);
+            claimed_rhs.insert(*r);
+            tentative.insert((*l, *r), *details);
+        }
+        Mapping::new(self.details, tentative)
+    }
 }
 
-#[derive(Debug, Eq)]
+/// The opcodes of a function along with its sorted callees.
+type Signature<'a> = (&'a [Mnemonic], Vec<Option<u64>>);
+
+#[derive(Debug)]
 struct PendingItem {
     pair: (u64, u64),
-    dist: usize,
-    mappings: Vec<(u64, u64)>,
+    origin: Origin,
+    star: StarMatch,
 }
 
 impl PendingItem {
-    fn new(pair: (u64, u64), dist: usize, mappings: Vec<(u64, u64)>) -> Self {
-        Self { pair, dist, mappings }
+    fn new(pair: (u64, u64), origin: Origin, star: StarMatch) -> Self {
+        Self { pair, origin, star }
+    }
+
+    fn details(&self) -> MatchDetails {
+        MatchDetails {
+            distance: self.star.dist,
+            opcode_distance: self.star.opcode_dist,
+            conflicts: self.star.conflicts,
+            origin: self.origin,
+        }
     }
 }
 
 impl PartialEq for PendingItem {
     fn eq(&self, other: &Self) -> bool {
-        self.dist == other.dist
+        self.star.dist == other.star.dist
     }
 }
 
+impl Eq for PendingItem {}
+
 impl PartialOrd for PendingItem {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -87,28 +293,166 @@ impl PartialOrd for PendingItem {
 
 impl Ord for PendingItem {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.dist.cmp(&other.dist).reverse()
+        self.star.dist.total_cmp(&other.star.dist).reverse()
     }
 }
 
+/// Details about how a pair of functions was matched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchDetails {
+    /// The cost of matching the stars of the functions, lower values mean higher confidence.
+    pub distance: f64,
+    /// The distance between the opcodes of the functions divided by the length of the longer
+    /// sequence, between 0 and 1.
+    pub opcode_distance: f64,
+    /// The number of conflicting mappings between the edges of the stars.
+    pub conflicts: usize,
+    /// The origin of the match.
+    pub origin: Origin,
+}
+
+/// Describes what proposed a matched pair of functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The pair was one of the seeds.
+    Seed,
+    /// The pair was proposed by matching the callees or the callers of the given pair.
+    Parent(u64, u64),
+    /// The pair was found among the functions left unmatched after the propagation stopped.
+    Rematch,
+}
+
 /// A mapping between two call graphs.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Mapping {
-    set: BTreeSet<(u64, u64)>,
+    set: BTreeMap<(u64, u64), MatchDetails>,
+    rhs_index: BTreeMap<u64, u64>,
+    tentative: BTreeMap<(u64, u64), MatchDetails>,
 }
 
 impl Mapping {
-    /// Returns a displayable representation of the mapping.
-    pub fn format(&self, segment_base_lhs: u64, segment_base_rhs: u64) -> impl fmt::Display + '_ {
-        Deferred(move |f: &mut fmt::Formatter<'_>| {
-            for (l, r) in &self.set {
-                writeln!(f, "{:X}, {:X}", segment_base_lhs + l, segment_base_rhs + r)?;
-            }
-            Ok(())
-        })
+    fn new(set: BTreeMap<(u64, u64), MatchDetails>, tentative: BTreeMap<(u64, u64), MatchDetails>) -> Self {
+        let rhs_index = set.keys().map(|&(l, r)| (r, l)).collect();
+        Self {
+            set,
+            rhs_index,
+            tentative,
+        }
+    }
+
+    /// Returns the right-hand side function matched with the given left-hand side function.
+    pub fn get_lhs(&self, lhs: u64) -> Option<u64> {
+        let ((_, rhs), _) = self.set.range((lhs, u64::MIN)..=(lhs, u64::MAX)).next()?;
+        Some(*rhs)
+    }
+
+    /// Returns the left-hand side function matched with the given right-hand side function.
+    pub fn get_rhs(&self, rhs: u64) -> Option<u64> {
+        self.rhs_index.get(&rhs).copied()
+    }
+
+    /// Returns the details of the match between two functions if they're matched.
+    pub fn details(&self, lhs: u64, rhs: u64) -> Option<&MatchDetails> {
+        self.set.get(&(lhs, rhs))
+    }
+
+    /// Returns the number of matched pairs.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    /// Checks whether there are no matched pairs.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// Returns a mapping with the sides swapped. Panics when a right-hand side function is
+    /// matched more than once, which the matching never produces.
+    pub fn inverse(&self) -> Self {
+        assert_eq!(self.rhs_index.len(), self.set.len(), "the mapping isn't injective");
+        let invert = |set: &BTreeMap<(u64, u64), MatchDetails>| {
+            set.iter()
+                .map(|(&(l, r), details)| {
+                    let origin = match details.origin {
+                        Origin::Parent(pl, pr) => Origin::Parent(pr, pl),
+                        origin => origin,
+                    };
+                    ((r, l), MatchDetails { origin, ..*details })
+                })
+                .collect()
+        };
+        Self::new(invert(&self.set), invert(&self.tentative))
+    }
+
+    /// Returns the functions of the left-hand side metadata that weren't matched, sorted by
+    /// address.
+    pub fn unmatched_lhs(&self, lhs: &CodeMetadata) -> Vec<u64> {
+        let mut unmatched: Vec<_> = lhs.functions().filter(|&addr| self.get_lhs(addr).is_none()).collect();
+        unmatched.sort_unstable();
+        unmatched
+    }
+
+    /// Returns the functions of the right-hand side metadata that weren't matched, sorted by
+    /// address.
+    pub fn unmatched_rhs(&self, rhs: &CodeMetadata) -> Vec<u64> {
+        let mut unmatched: Vec<_> = rhs.functions().filter(|&addr| self.get_rhs(addr).is_none()).collect();
+        unmatched.sort_unstable();
+        unmatched
+    }
+
+    /// Returns an iterator over the matched pairs of function addresses sorted by the
+    /// left-hand side address.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.set.keys().copied()
+    }
+
+    /// Returns an iterator over the matched pairs of function addresses along with the details
+    /// of each match.
+    pub fn iter_with_details(&self) -> impl Iterator<Item = ((u64, u64), &MatchDetails)> + '_ {
+        self.set.iter().map(|(&pair, details)| (pair, details))
+    }
+
+    /// Returns an iterator over the tentative pairs sorted by the left-hand side address. These
+    /// pairs were above the maximum distance, so they aren't part of the matched pairs.
+    pub fn tentative(&self) -> impl Iterator<Item = ((u64, u64), &MatchDetails)> + '_ {
+        self.tentative.iter().map(|(&pair, details)| (pair, details))
+    }
+
+    /// Returns a displayable representation of the mapping. Each line holds the addresses of
+    /// a pair followed by its distance, opcode distance, number of conflicts and its origin,
+    /// either `seed`, `rematch` or the addresses of the pair that proposed it separated by
+    /// a colon.
+    pub fn format(&self) -> impl fmt::Display + '_ {
+        format_pairs(&self.set)
+    }
+
+    /// Returns a displayable representation of the tentative pairs in the same format as
+    /// [`Mapping::format`].
+    pub fn format_tentative(&self) -> impl fmt::Display + '_ {
+        format_pairs(&self.tentative)
     }
 }
 
+fn format_pairs(set: &BTreeMap<(u64, u64), MatchDetails>) -> impl fmt::Display + '_ {
+    Deferred(move |f: &mut fmt::Formatter<'_>| {
+        for ((l, r), details) in set {
+            write!(
+                f,
+                "{:X}, {:X}, {:.3}, {:.3}, {}, ",
+                l, r, details.distance, details.opcode_distance, details.conflicts
+            )?;
+            match details.origin {
+                Origin::Seed => writeln!(f, "seed")?,
+                Origin::Rematch => writeln!(f, "rematch")?,
+                Origin::Parent(pl, pr) => writeln!(f, "{:X}:{:X}", pl, pr)?,
+            }
+        }
+        Ok(())
+    })
+}
+
 struct Deferred<F>(F);
 
 impl<F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result> fmt::Display for Deferred<F> {
@@ -116,3 +460,243 @@ impl<F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result> fmt::Display for Deferred<F>
         self.0(f)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use iced_x86::Mnemonic;
+
+    use super::*;
+    use crate::heuristics::CallOrder;
+    use crate::object::FunctionMetadata;
+
+    fn test_mapping() -> Mapping {
+        let details = |origin| MatchDetails {
+            distance: 1.,
+            opcode_distance: 0.5,
+            conflicts: 0,
+            origin,
+        };
+        Mapping::new(
+            [
+                ((0x10, 0x20), details(Origin::Seed)),
+                ((0x11, 0x23), details(Origin::Parent(0x10, 0x20))),
+                ((0x12, 0x22), details(Origin::Parent(0x10, 0x20))),
+            ]
+            .into_iter()
+            .collect(),
+            BTreeMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_mapping_lookup() {
+        let mapping = test_mapping();
+        assert_eq!(mapping.len(), 3);
+        assert_eq!(mapping.get_lhs(0x11), Some(0x23));
+        assert_eq!(mapping.get_lhs(0x20), None);
+        assert_eq!(mapping.get_rhs(0x22), Some(0x12));
+        assert_eq!(mapping.get_rhs(0x10), None);
+        assert_eq!(
+            mapping.iter().collect::<Vec<_>>(),
+            &[(0x10, 0x20), (0x11, 0x23), (0x12, 0x22)]
+        );
+    }
+
+    #[test]
+    fn test_mapping_format() {
+        let mut mapping = test_mapping();
+        mapping.set.get_mut(&(0x10, 0x20)).unwrap().distance = 7. / 3.;
+        assert_eq!(
+            mapping.format().to_string().lines().collect::<Vec<_>>(),
+            &[
+                "10, 20, 2.333, 0.500, 0, seed",
+                "11, 23, 1.000, 0.500, 0, 10:20",
+                "12, 22, 1.000, 0.500, 0, 10:20",
+            ]
+        );
+        assert_eq!(mapping.format_tentative().to_string(), "");
+    }
+
+    #[test]
+    fn test_mapping_inverse() {
+        let inverse = test_mapping().inverse();
+        assert_eq!(
+            inverse.iter().collect::<Vec<_>>(),
+            &[(0x20, 0x10), (0x22, 0x12), (0x23, 0x11)]
+        );
+        assert_eq!(inverse.details(0x23, 0x11).unwrap().origin, Origin::Parent(0x20, 0x10));
+        assert_eq!(inverse.get_rhs(0x11), Some(0x23));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mapping_inverse_not_injective() {
+        let mut mapping = test_mapping();
+        let details = mapping.set[&(0x10, 0x20)];
+        mapping.set.insert((0x13, 0x20), details);
+        mapping.inverse();
+    }
+
+    #[test]
+    fn test_propagate_to_callers() {
+        let metadata = |base| {
+            CodeMetadata::with_calls(
+                base,
+                &[(1, 2), (1, 3)],
+                &[
+                    (1, &[Mnemonic::Call, Mnemonic::Call, Mnemonic::Ret]),
+                    (2, &[Mnemonic::Mov, Mnemonic::Ret]),
+                    (3, &[Mnemonic::Ret]),
+                ],
+            )
+        };
+        let (lhs, rhs) = (metadata(0), metadata(10));
+        let mapping = belief_prop(&lhs, &rhs, [(3, 13)], &CallOrder, &MatchConfig::default());
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (2, 12), (3, 13)]);
+        assert_eq!(mapping.details(1, 11).unwrap().origin, Origin::Parent(3, 13));
+        assert_eq!(mapping.details(2, 12).unwrap().origin, Origin::Parent(1, 11));
+    }
+
+    #[test]
+    fn test_skip_callers_of_imports() {
+        let metadata = |base| {
+            let mut metadata = CodeMetadata::with_calls(
+                base,
+                &[],
+                &[
+                    (1, &[Mnemonic::Call, Mnemonic::Ret]),
+                    (2, &[Mnemonic::Push, Mnemonic::Call, Mnemonic::Ret]),
+                ],
+            );
+            metadata.call_graph.add_edge(base + 1, base + 3, EdgeKind::Import);
+            metadata.call_graph.add_edge(base + 2, base + 3, EdgeKind::Import);
+            metadata
+                .functions
+                .insert(base + 3, FunctionMetadata::imported("ExitProcess"));
+            metadata
+        };
+        let (lhs, rhs) = (metadata(0), metadata(10));
+        let mapping = belief_prop(&lhs, &rhs, [(3, 13)], &CallOrder, &MatchConfig::default());
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(3, 13)]);
+    }
+
+    #[test]
+    fn test_match_once() {
+        let metadata = |base| {
+            CodeMetadata::with_calls(
+                base,
+                &[(1, 3), (2, 3)],
+                &[
+                    (1, &[Mnemonic::Call, Mnemonic::Ret]),
+                    (2, &[Mnemonic::Push, Mnemonic::Call, Mnemonic::Ret]),
+                    (3, &[Mnemonic::Ret]),
+                ],
+            )
+        };
+        let (lhs, rhs) = (metadata(0), metadata(10));
+        let mapping = belief_prop(&lhs, &rhs, [(1, 11), (2, 11)], &CallOrder, &MatchConfig::default());
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (2, 12), (3, 13)]);
+        assert_eq!(mapping.details(2, 12).unwrap().origin, Origin::Parent(3, 13));
+    }
+
+    #[test]
+    fn test_rematch_leftovers() {
+        let metadata = |base, opcodes| {
+            CodeMetadata::with_calls(
+                base,
+                &[(1, 2)],
+                &[
+                    (1, &[Mnemonic::Call, Mnemonic::Ret]),
+                    (2, opcodes),
+                    (5, &[Mnemonic::Push, Mnemonic::Pop, Mnemonic::Ret]),
+                    (6, &[Mnemonic::Nop, Mnemonic::Ret]),
+                    (7, &[Mnemonic::Nop, Mnemonic::Ret]),
+                ],
+            )
+        };
+        let lhs = metadata(0, &[Mnemonic::Mov, Mnemonic::Ret]);
+        let rhs = metadata(10, &[Mnemonic::Xor, Mnemonic::Inc, Mnemonic::Ret]);
+
+        let mapping = belief_prop(&lhs, &rhs, [(1, 11)], &CallOrder, &MatchConfig::default());
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (2, 12)]);
+
+        let mapping = belief_prop_with_rematch(&lhs, &rhs, [(1, 11)], &CallOrder, &MatchConfig::default());
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (2, 12), (5, 15)]);
+        assert_eq!(mapping.details(5, 15).unwrap().origin, Origin::Rematch);
+
+        // the rejected pair isn't proposed again by the rematching
+        let config = MatchConfig {
+            max_distance: Some(0.5),
+            ..MatchConfig::default()
+        };
+        let mapping = belief_prop_with_rematch(&lhs, &rhs, [(1, 11)], &CallOrder, &config);
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (5, 15)]);
+    }
+
+    #[test]
+    fn test_max_distance() {
+        let metadata = |base, opcodes| {
+            CodeMetadata::with_calls(
+                base,
+                &[(1, 2), (2, 3)],
+                &[
+                    (1, &[Mnemonic::Call, Mnemonic::Ret]),
+                    (2, opcodes),
+                    (3, &[Mnemonic::Ret]),
+                ],
+            )
+        };
+        let lhs = metadata(0, &[Mnemonic::Mov, Mnemonic::Call, Mnemonic::Ret]);
+        let rhs = metadata(10, &[Mnemonic::Push, Mnemonic::Pop, Mnemonic::Nop, Mnemonic::Call]);
+
+        let mut config = MatchConfig::default();
+        let mapping = belief_prop(&lhs, &rhs, [(1, 11)], &CallOrder, &config);
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (2, 12), (3, 13)]);
+
+        config.max_distance = Some(0.5);
+        let mapping = belief_prop(&lhs, &rhs, [(1, 11)], &CallOrder, &config);
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11)]);
+        assert_eq!(mapping.tentative().count(), 0);
+
+        config.keep_tentative = true;
+        let mapping = belief_prop(&lhs, &rhs, [(1, 11)], &CallOrder, &config);
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11)]);
+        assert_eq!(
+            mapping.tentative().map(|(pair, _)| pair).collect::<Vec<_>>(),
+            &[(2, 12)]
+        );
+        assert_eq!(mapping.inverse().tentative().next().unwrap().0, (12, 2));
+    }
+
+    #[test]
+    fn test_max_distance_imports() {
+        let metadata = |base, import| {
+            let mut metadata = CodeMetadata::with_calls(base, &[(1, 2)], &[(1, &[Mnemonic::Call, Mnemonic::Ret])]);
+            metadata.functions.insert(base + 2, FunctionMetadata::imported(import));
+            metadata
+        };
+        let config = MatchConfig {
+            max_distance: Some(0.5),
+            ..MatchConfig::default()
+        };
+        let lhs = metadata(0, "CreateFileW");
+        let mapping = belief_prop(&lhs, &metadata(10, "ExitProcess"), [(1, 11)], &CallOrder, &config);
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11)]);
+        let mapping = belief_prop(&lhs, &metadata(10, "CreateFileA"), [(1, 11)], &CallOrder, &config);
+        assert_eq!(mapping.iter().collect::<Vec<_>>(), &[(1, 11), (2, 12)]);
+    }
+
+    #[test]
+    fn test_mapping_unmatched() {
+        let metadata = |addrs: &[u64]| {
+            let functions: Vec<_> = addrs.iter().map(|&addr| (addr, &[][..])).collect();
+            CodeMetadata::with_calls(0, &[], &functions)
+        };
+        let mapping = test_mapping();
+        assert_eq!(
+            mapping.unmatched_lhs(&metadata(&[0x13, 0x10, 0x11, 0x14])),
+            &[0x13, 0x14]
+        );
+        assert_eq!(mapping.unmatched_rhs(&metadata(&[0x20, 0x21, 0x22])), &[0x21]);
+    }
+}
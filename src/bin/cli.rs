@@ -1,16 +1,23 @@
 use std::error::Error as StdError;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
 
-use clap::Parser;
-use graphmat::{belief_prop, heuristics, CodeMetadata, ObjectCode};
+use clap::{Parser, Subcommand};
+use graphmat::diff::BlockChange;
+use graphmat::{
+    belief_prop, belief_prop_with_rematch, diff, heuristics, pattern, seeds, CodeMetadata, Granularity, LoadOptions,
+    MatchConfig, ObjectCode,
+};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// The file to load initial mappings from.
-    #[arg(short, long)]
+    #[arg(long)]
     seeds: Option<PathBuf>,
     /// The first object file to compare.
     #[arg(short, long)]
@@ -19,8 +26,95 @@ struct Args {
     #[arg(short, long)]
     second: PathBuf,
     /// The path to write the mapping to as a CSV file.
+    #[arg(short, long, required = true)]
+    output: Option<PathBuf>,
+    /// Use symbol tables to find functions and seed the matching with symbols found in both files.
+    #[arg(long)]
+    symbols: bool,
+    /// Build control flow graphs of functions and compare functions by their structure.
+    #[arg(long)]
+    cfg: bool,
+    /// The path to write the changes of basic blocks in matched functions to as a CSV file.
+    /// Implies --cfg.
+    #[arg(long)]
+    blocks: Option<PathBuf>,
+    /// Pair up functions left unmatched with unique signatures and continue the matching from them.
+    #[arg(long)]
+    rematch: bool,
+    /// Seed the matching with functions whose code hashes are unique and equal in both files.
+    /// Functions that can't be reached from the entrypoint are loaded for it as well.
+    #[arg(long)]
+    hash_seeds: bool,
+    /// Hash only the opcodes of functions, ignoring their operands.
+    #[arg(long, requires = "hash_seeds")]
+    mask_operands: bool,
+    /// Seed the matching with functions that reference a string unique to each file.
+    /// Functions that can't be reached from the entrypoint are loaded for it as well.
+    #[arg(long)]
+    seed_strings: bool,
+    /// The file to load named IDA-style patterns from, which are used to seed the matching.
+    #[arg(long)]
+    patterns: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value = "code-size,call-order",
+        help = format!(
+            "The comma-separated heuristics used to match calls, each optionally followed by `=` and a weight. \
+             Available heuristics: {}",
+            heuristics::NAMES.join(", ")
+        )
+    )]
+    heuristics: String,
+    /// The level of detail at which instructions are compared: mnemonic or operands, which also
+    /// compares the kinds of operands and the classes of registers.
+    #[arg(long, default_value = "mnemonic")]
+    granularity: Granularity,
+    /// The cost of a call that isn't matched to any call of the other function.
+    #[arg(long, value_parser = parse_non_negative)]
+    insert_delete_cost: Option<f64>,
+    /// The cost of a matched call that disagrees with the matches of its neighbours.
+    #[arg(long, value_parser = parse_non_negative)]
+    conflict_cost: Option<f64>,
+    /// The weight of the distance between the instructions of two functions.
+    #[arg(long, value_parser = parse_non_negative)]
+    opcode_weight: Option<f64>,
+    /// The weight of the distance between the control flow graphs of two functions.
+    #[arg(long, value_parser = parse_non_negative)]
+    cfg_weight: Option<f64>,
+    /// Divide the distances between the instructions and between the control flow graphs of two
+    /// functions by the size of the larger function.
+    #[arg(long)]
+    normalize: bool,
+    /// The maximum normalized distance, in the range 0..=1, between the opcodes of the pairs
+    /// proposed by the propagation, or between their names for imports. Pairs above it aren't
+    /// matched.
+    #[arg(long, value_parser = parse_max_distance)]
+    max_distance: Option<f64>,
+    /// The file to write the pairs above the maximum distance to instead of rejecting them.
+    #[arg(long, requires = "max_distance")]
+    tentative: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the differences between the instructions of two functions.
+    Diff(DiffArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// The hexadecimal address of the function in the first object file.
+    #[arg(long, value_parser = parse_address)]
+    lhs: u64,
+    /// The hexadecimal address of the function in the second object file.
+    #[arg(long, value_parser = parse_address, required_unless_present = "mapping")]
+    rhs: Option<u64>,
+    /// The mapping file to look up the function in the second object file in.
     #[arg(short, long)]
-    output: PathBuf,
+    mapping: Option<PathBuf>,
+    /// Show the instructions side by side instead of as a unified diff.
+    #[arg(long)]
+    side_by_side: bool,
 }
 
 fn main() -> Result<(), Box<dyn StdError>> {
@@ -28,50 +122,164 @@ fn main() -> Result<(), Box<dyn StdError>> {
 
     let lhs_file = fs::read(args.first)?;
     let lhs_file = object::read::File::parse(&lhs_file[..])?;
-    let lhs_file = ObjectCode::load(&lhs_file)?;
+    let lhs_file = load_object(&lhs_file, args.symbols)?;
     let rhs_file = fs::read(args.second)?;
     let rhs_file = object::read::File::parse(&rhs_file[..])?;
-    let rhs_file = ObjectCode::load(&rhs_file)?;
+    let rhs_file = load_object(&rhs_file, args.symbols)?;
 
-    let seeds = args
+    if let Some(Command::Diff(cmd)) = args.command {
+        return print_diff(&lhs_file, &rhs_file, &cmd);
+    }
+    let heuristics = heuristics::parse_list(&args.heuristics)?;
+    let defaults = MatchConfig::default();
+    let config = MatchConfig {
+        insert_delete_cost: args.insert_delete_cost.unwrap_or(defaults.insert_delete_cost),
+        conflict_cost: args.conflict_cost.unwrap_or(defaults.conflict_cost),
+        opcode_weight: args.opcode_weight.unwrap_or(defaults.opcode_weight),
+        cfg_weight: args.cfg_weight.unwrap_or(defaults.cfg_weight),
+        normalize: args.normalize,
+        granularity: args.granularity,
+        max_distance: args.max_distance,
+        keep_tentative: args.tentative.is_some(),
+    };
+
+    let mut seeds = args
         .seeds
-        .map(|path| load_seeds(&path, lhs_file.text_section_base(), rhs_file.text_section_base()))
+        .map(|path| load_seeds(&path))
         .transpose()?
         .unwrap_or_default();
+    seeds.extend(seeds::imports(&lhs_file, &rhs_file));
+    if args.symbols {
+        seeds.extend(seeds::symbols(&lhs_file, &rhs_file));
+    }
+    if let Some(path) = &args.patterns {
+        let signatures = pattern::parse_signatures(&fs::read_to_string(path)?)?;
+        seeds.extend(seeds::patterns(&lhs_file, &rhs_file, &signatures));
+    }
 
-    let lhs = CodeMetadata::load(&lhs_file, seeds.iter().map(|&(s, _)| s))?;
-    let rhs = CodeMetadata::load(&rhs_file, seeds.iter().map(|&(_, s)| s))?;
+    let options = LoadOptions {
+        cfg: args.cfg || args.blocks.is_some(),
+        function_starts: args.hash_seeds || args.seed_strings,
+    };
+    let lhs = CodeMetadata::load_with_options(&lhs_file, seeds.iter().map(|&(s, _)| s), &options)?;
+    let rhs = CodeMetadata::load_with_options(&rhs_file, seeds.iter().map(|&(_, s)| s), &options)?;
+    if args.hash_seeds {
+        seeds.extend(seeds::hashes(&lhs, &rhs, args.mask_operands));
+    }
+    if args.seed_strings {
+        seeds.extend(seeds::strings(&lhs, &rhs));
+    }
 
-    let res = belief_prop(
-        &lhs,
-        &rhs,
-        [(lhs_file.entrypoint(), rhs_file.entrypoint())]
-            .into_iter()
-            .chain(seeds),
-        &heuristics![heuristics::RelativeCodeSize, heuristics::CallOrder],
-    );
+    let seeds = [(lhs_file.entrypoint(), rhs_file.entrypoint())]
+        .into_iter()
+        .chain(seeds);
+    let res = if args.rematch {
+        belief_prop_with_rematch(&lhs, &rhs, seeds, &heuristics, &config)
+    } else {
+        belief_prop(&lhs, &rhs, seeds, &heuristics, &config)
+    };
+
+    let mut out = BufWriter::new(File::create(args.output.expect("output is required"))?);
+
+    writeln!(out, "{}", res.format())?;
+
+    if let Some(path) = args.tentative {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", res.format_tentative())?;
+    }
+
+    if let Some(path) = args.blocks {
+        let mut out = BufWriter::new(File::create(path)?);
+        for func in diff::blocks(&lhs, &rhs, res.iter()) {
+            for change in func.blocks {
+                let (kind, l, r) = match change {
+                    BlockChange::Matched(l, r) => ("matched", Some(l), Some(r)),
+                    BlockChange::Modified(l, r) => ("modified", Some(l), Some(r)),
+                    BlockChange::Added(r) => ("added", None, Some(r)),
+                    BlockChange::Removed(l) => ("removed", Some(l), None),
+                };
+                let l = l.map(|addr| format!("{:X}", addr)).unwrap_or_default();
+                let r = r.map(|addr| format!("{:X}", addr)).unwrap_or_default();
+                writeln!(out, "{:X}, {:X}, {}, {}, {}", func.lhs, func.rhs, kind, l, r)?;
+            }
+        }
+    }
 
-    let mut out = BufWriter::new(File::create(args.output)?);
+    Ok(())
+}
 
-    writeln!(
-        out,
-        "{}",
-        res.format(lhs_file.text_section_base(), rhs_file.text_section_base())
-    )?;
+fn print_diff(lhs_file: &ObjectCode<'_>, rhs_file: &ObjectCode<'_>, args: &DiffArgs) -> Result<(), Box<dyn StdError>> {
+    let rhs_addr = match (args.rhs, &args.mapping) {
+        (Some(addr), _) => addr,
+        (None, Some(path)) => load_seeds(path)?
+            .into_iter()
+            .find_map(|(l, r)| (l == args.lhs).then_some(r))
+            .ok_or("the function is not in the mapping")?,
+        (None, None) => unreachable!(),
+    };
 
+    let lhs = CodeMetadata::load(lhs_file, [args.lhs])?;
+    let rhs = CodeMetadata::load(rhs_file, [rhs_addr])?;
+    let lhs = lhs
+        .instructions(lhs_file, args.lhs)
+        .ok_or("no function in the first file")?;
+    let rhs = rhs
+        .instructions(rhs_file, rhs_addr)
+        .ok_or("no function in the second file")?;
+
+    let diff = diff::instructions(&lhs, &rhs);
+    let mut out = io::stdout().lock();
+    if args.side_by_side {
+        write!(out, "{}", diff.side_by_side())?;
+    } else {
+        write!(out, "{}", diff.unified())?;
+    }
     Ok(())
 }
 
-fn load_seeds(path: &Path, lhs_base: u64, rhs_base: u64) -> Result<Vec<(u64, u64)>, Box<dyn StdError>> {
+fn load_object<'data>(file: &object::read::File<'data>, symbols: bool) -> Result<ObjectCode<'data>, Box<dyn StdError>> {
+    let obj = if symbols {
+        ObjectCode::load_with_symbols(file)?
+    } else {
+        ObjectCode::load(file)?
+    };
+    Ok(obj)
+}
+
+fn parse_address(str: &str) -> Result<u64, ParseIntError> {
+    u64::from_str_radix(str.trim_start_matches("0x"), 16)
+}
+
+fn parse_non_negative(str: &str) -> Result<f64, String> {
+    match str.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0. => Ok(value),
+        Ok(_) => Err("expected a finite non-negative number".into()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_max_distance(str: &str) -> Result<f64, String> {
+    match str.parse::<f64>() {
+        Ok(dist) if (0. ..=1.).contains(&dist) => Ok(dist),
+        Ok(_) => Err("expected a distance in the range 0..=1".into()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn load_seeds(path: &Path) -> Result<Vec<(u64, u64)>, Box<dyn StdError>> {
     let mut seeds = vec![];
     for line in BufReader::new(File::open(path)?).lines() {
         let line = line?;
         if line.trim().is_empty() {
             continue;
         }
-        let (lhs, rhs) = line.split_once(',').expect("invalid seed file");
-        let lhs = u64::from_str_radix(lhs.trim(), 16)? - lhs_base;
-        let rhs = u64::from_str_radix(rhs.trim(), 16)? - rhs_base;
+        // mappings written by the tool can be used as seeds, so columns past the first two are ignored
+        let mut fields = line.split(',');
+        let (Some(lhs), Some(rhs)) = (fields.next(), fields.next()) else {
+            panic!("invalid seed file");
+        };
+        let lhs = u64::from_str_radix(lhs.trim(), 16)?;
+        let rhs = u64::from_str_radix(rhs.trim(), 16)?;
         seeds.push((lhs, rhs));
     }
     Ok(seeds)
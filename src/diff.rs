@@ -0,0 +1,335 @@
+use std::fmt;
+
+use bumpalo::collections::{CollectIn, Vec as BumpVec};
+use bumpalo::Bump;
+use hashbrown::HashMap;
+use iced_x86::{Formatter, Instruction, IntelFormatter};
+
+use crate::levenshtein::{levenshtein_matrix, Edit};
+use crate::object::{normalize_instruction, CodeMetadata, ControlFlowGraph};
+
+/// The change of a basic block between two versions of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockChange {
+    /// The blocks at both addresses have the same opcodes.
+    Matched(u64, u64),
+    /// The blocks at both addresses are aligned, but their opcodes differ.
+    Modified(u64, u64),
+    /// The block at the address only exists in the second function.
+    Added(u64),
+    /// The block at the address only exists in the first function.
+    Removed(u64),
+}
+
+/// The changes of basic blocks between two matched functions.
+#[derive(Debug, Clone)]
+pub struct FunctionDiff {
+    /// The address of the function in the first object.
+    pub lhs: u64,
+    /// The address of the function in the second object.
+    pub rhs: u64,
+    /// The changes of the blocks in the order of their addresses.
+    pub blocks: Vec<BlockChange>,
+}
+
+impl FunctionDiff {
+    /// Checks whether all blocks of the functions are matched.
+    pub fn is_unchanged(&self) -> bool {
+        self.blocks
+            .iter()
+            .all(|change| matches!(change, BlockChange::Matched(_, _)))
+    }
+}
+
+/// Aligns the basic blocks of each matched pair of functions. Pairs of functions without
+/// control flow graphs are skipped.
+pub fn blocks(
+    lhs: &CodeMetadata,
+    rhs: &CodeMetadata,
+    pairs: impl IntoIterator<Item = (u64, u64)>,
+) -> Vec<FunctionDiff> {
+    pairs
+        .into_iter()
+        .filter_map(|(l, r)| {
+            let blocks = match_blocks(lhs.cfg(l)?, rhs.cfg(r)?);
+            Some(FunctionDiff { lhs: l, rhs: r, blocks })
+        })
+        .collect()
+}
+
+/// Aligns the basic blocks of two control flow graphs in the order of their addresses.
+/// Blocks are compared by their signatures, which are equal for blocks with the same opcodes.
+pub fn match_blocks(lhs: &ControlFlowGraph, rhs: &ControlFlowGraph) -> Vec<BlockChange> {
+    let bump = Bump::new();
+    let mut signatures = HashMap::new();
+    let mut signature = |opcodes| {
+        let next = signatures.len();
+        *signatures.entry(opcodes).or_insert(next)
+    };
+    let labels_l: BumpVec<'_, usize> = lhs
+        .blocks()
+        .iter()
+        .map(|block| signature(block.opcodes()))
+        .collect_in(&bump);
+    let labels_r: BumpVec<'_, usize> = rhs
+        .blocks()
+        .iter()
+        .map(|block| signature(block.opcodes()))
+        .collect_in(&bump);
+
+    align(&labels_l, &labels_r)
+        .into_iter()
+        .map(|alignment| match alignment {
+            (Some(i), Some(j)) if labels_l[i] == labels_r[j] => {
+                BlockChange::Matched(lhs.blocks()[i].address(), rhs.blocks()[j].address())
+            }
+            (Some(i), Some(j)) => BlockChange::Modified(lhs.blocks()[i].address(), rhs.blocks()[j].address()),
+            (Some(i), None) => BlockChange::Removed(lhs.blocks()[i].address()),
+            (None, Some(j)) => BlockChange::Added(rhs.blocks()[j].address()),
+            (None, None) => unreachable!(),
+        })
+        .collect()
+}
+
+/// The change of an instruction between two versions of a function.
+#[derive(Debug, Clone, Copy)]
+pub enum InstructionChange {
+    /// The instructions are equal apart from their addresses.
+    Same(Instruction, Instruction),
+    /// The instructions are aligned, but they differ.
+    Substituted(Instruction, Instruction),
+    /// The instruction only exists in the second function.
+    Inserted(Instruction),
+    /// The instruction only exists in the first function.
+    Deleted(Instruction),
+}
+
+/// The changes of instructions between two functions.
+#[derive(Debug, Clone)]
+pub struct InstructionDiff {
+    changes: Vec<InstructionChange>,
+}
+
+impl InstructionDiff {
+    /// Returns the changes in the order of the instructions.
+    #[inline]
+    pub fn changes(&self) -> &[InstructionChange] {
+        &self.changes
+    }
+
+    /// Returns a displayable unified diff. Deleted and inserted instructions are marked with
+    /// `-` and `+`, while the old and new versions of substituted instructions are marked
+    /// with `<` and `>`.
+    pub fn unified(&self) -> impl fmt::Display + '_ {
+        Unified(&self.changes)
+    }
+
+    /// Returns a displayable side-by-side diff. Substituted, deleted and inserted instructions
+    /// are marked with `|`, `<` and `>`.
+    pub fn side_by_side(&self) -> impl fmt::Display + '_ {
+        SideBySide(&self.changes)
+    }
+}
+
+/// Aligns two sequences of instructions using an optimal edit script. Instructions are
+/// compared without their branch targets and instruction pointer relative addresses, which
+/// change whenever code is moved.
+pub fn instructions(lhs: &[Instruction], rhs: &[Instruction]) -> InstructionDiff {
+    let bump = Bump::new();
+    let normalized_l: BumpVec<'_, _> = lhs.iter().map(normalize_instruction).collect_in(&bump);
+    let normalized_r: BumpVec<'_, _> = rhs.iter().map(normalize_instruction).collect_in(&bump);
+
+    let changes = align(&normalized_l, &normalized_r)
+        .into_iter()
+        .map(|alignment| match alignment {
+            (Some(i), Some(j)) if normalized_l[i] == normalized_r[j] => InstructionChange::Same(lhs[i], rhs[j]),
+            (Some(i), Some(j)) => InstructionChange::Substituted(lhs[i], rhs[j]),
+            (Some(i), None) => InstructionChange::Deleted(lhs[i]),
+            (None, Some(j)) => InstructionChange::Inserted(rhs[j]),
+            (None, None) => unreachable!(),
+        })
+        .collect();
+    InstructionDiff { changes }
+}
+
+/// Aligns two sequences using an optimal edit script. Each alignment holds the index of
+/// an element of the first sequence, of the second sequence or of both when the elements are
+/// aligned with each other, in the order of the sequences.
+fn align<A: PartialEq>(lhs: &[A], rhs: &[A]) -> Vec<(Option<usize>, Option<usize>)> {
+    let bump = Bump::new();
+    let mat = levenshtein_matrix(lhs, rhs, &bump);
+    // The edits are produced starting from the end, the index of the right-hand side element is
+    // tracked alongside, because it's not included in noops.
+    let mut j = rhs.len();
+    let mut alignments = vec![];
+    for (i, edit) in mat.edits().with_indices() {
+        let alignment = match edit {
+            Edit::Insert(_) => {
+                j -= 1;
+                (None, Some(j))
+            }
+            Edit::Delete => (Some(i), None),
+            Edit::Substitute(_) | Edit::Noop => {
+                j -= 1;
+                (Some(i), Some(j))
+            }
+        };
+        alignments.push(alignment);
+    }
+    alignments.reverse();
+    alignments
+}
+
+/// Width of the left column of a side-by-side diff.
+const COLUMN_WIDTH: usize = 48;
+
+struct Unified<'a>(&'a [InstructionChange]);
+
+impl fmt::Display for Unified<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut formatter = IntelFormatter::new();
+        for change in self.0 {
+            match change {
+                InstructionChange::Same(lhs, _) => writeln!(f, "  {}", format_line(&mut formatter, lhs))?,
+                InstructionChange::Substituted(lhs, rhs) => {
+                    writeln!(f, "< {}", format_line(&mut formatter, lhs))?;
+                    writeln!(f, "> {}", format_line(&mut formatter, rhs))?;
+                }
+                InstructionChange::Inserted(rhs) => writeln!(f, "+ {}", format_line(&mut formatter, rhs))?,
+                InstructionChange::Deleted(lhs) => writeln!(f, "- {}", format_line(&mut formatter, lhs))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct SideBySide<'a>(&'a [InstructionChange]);
+
+impl fmt::Display for SideBySide<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut formatter = IntelFormatter::new();
+        for change in self.0 {
+            let (lhs, marker, rhs) = match change {
+                InstructionChange::Same(lhs, rhs) => (Some(lhs), ' ', Some(rhs)),
+                InstructionChange::Substituted(lhs, rhs) => (Some(lhs), '|', Some(rhs)),
+                InstructionChange::Inserted(rhs) => (None, '>', Some(rhs)),
+                InstructionChange::Deleted(lhs) => (Some(lhs), '<', None),
+            };
+            let lhs = lhs.map(|insn| format_line(&mut formatter, insn)).unwrap_or_default();
+            let rhs = rhs.map(|insn| format_line(&mut formatter, insn)).unwrap_or_default();
+            writeln!(f, "{:<width$} {} {}", lhs, marker, rhs, width = COLUMN_WIDTH)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_line(formatter: &mut IntelFormatter, instruction: &Instruction) -> String {
+    let mut line = format!("{:X}  ", instruction.ip());
+    formatter.format(instruction, &mut line);
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use iced_x86::{Decoder, DecoderOptions, Mnemonic};
+
+    use super::*;
+    use crate::object::CfgBuilder;
+
+    fn build(code: &[u8]) -> ControlFlowGraph {
+        let mut builder = CfgBuilder::default();
+        for instruction in Decoder::with_ip(64, code, 0x1000, DecoderOptions::NONE) {
+            builder.push(&instruction);
+        }
+        builder.build(0x1000)
+    }
+
+    #[test]
+    fn test_match_blocks() {
+        let lhs = build(&[
+            0x85, 0xFF, // test edi, edi
+            0x74, 0x03, // je 0x1007
+            0x31, 0xC0, // xor eax, eax
+            0xC3, // ret
+            0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0xC3, // ret
+        ]);
+        let rhs = build(&[
+            0x85, 0xFF, // test edi, edi
+            0x74, 0x05, // je 0x1009
+            0x31, 0xC0, // xor eax, eax
+            0xFF, 0xC0, // inc eax
+            0xC3, // ret
+            0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0xC3, // ret
+            0xCC, // int3
+        ]);
+        assert_eq!(
+            match_blocks(&lhs, &rhs),
+            &[
+                BlockChange::Matched(0x1000, 0x1000),
+                BlockChange::Modified(0x1004, 0x1004),
+                BlockChange::Matched(0x1007, 0x1009),
+                BlockChange::Added(0x100F),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_instructions() {
+        let decode = |code, ip| {
+            Decoder::with_ip(64, code, ip, DecoderOptions::NONE)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        let lhs = decode(
+            &[
+                0xE8, 0xFB, 0x00, 0x00, 0x00, // call 0x1100
+                0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+                0x31, 0xC9, // xor ecx, ecx
+                0xC3, // ret
+            ],
+            0x1000,
+        );
+        let rhs = decode(
+            &[
+                0xE8, 0xFB, 0x00, 0x00, 0x00, // call 0x2100
+                0xB8, 0x02, 0x00, 0x00, 0x00, // mov eax, 2
+                0xFF, 0xC0, // inc eax
+                0xC3, // ret
+            ],
+            0x2000,
+        );
+        let diff = instructions(&lhs, &rhs);
+        let changes: Vec<_> = diff
+            .changes()
+            .iter()
+            .map(|change| match change {
+                InstructionChange::Same(l, r) => ('=', l.mnemonic(), r.mnemonic()),
+                InstructionChange::Substituted(l, r) => ('|', l.mnemonic(), r.mnemonic()),
+                InstructionChange::Inserted(r) => ('+', Mnemonic::INVALID, r.mnemonic()),
+                InstructionChange::Deleted(l) => ('-', l.mnemonic(), Mnemonic::INVALID),
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            &[
+                ('=', Mnemonic::Call, Mnemonic::Call),
+                ('|', Mnemonic::Mov, Mnemonic::Mov),
+                ('|', Mnemonic::Xor, Mnemonic::Inc),
+                ('=', Mnemonic::Ret, Mnemonic::Ret),
+            ]
+        );
+        assert_eq!(
+            diff.unified().to_string().lines().collect::<Vec<_>>(),
+            &[
+                "  1000  call 0000000000001100h",
+                "< 1005  mov eax,1",
+                "> 2005  mov eax,2",
+                "< 100A  xor ecx,ecx",
+                "> 200A  inc eax",
+                "  100C  ret",
+            ]
+        );
+    }
+}
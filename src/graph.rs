@@ -1,46 +1,125 @@
+use std::collections::VecDeque;
 use std::hash::Hash;
 
+use hashbrown::hash_map::Entry;
+use hashbrown::HashMap;
 use ordered_multimap::list_ordered_multimap::EntryValues;
 use ordered_multimap::ListOrderedMultimap;
 
-/// A graph represented as an adjacency list.
+/// A graph represented as an adjacency list, along with a reverse adjacency list of the
+/// incoming edges of each vertex.
 #[derive(Debug, Default)]
-pub struct Graph<A>(ListOrderedMultimap<A, A>);
+pub struct Graph<A> {
+    outgoing: ListOrderedMultimap<A, Edge<A>>,
+    incoming: ListOrderedMultimap<A, Edge<A>>,
+}
 
-impl<A: Eq + PartialEq + Hash> Graph<A> {
+impl<A: Eq + PartialEq + Hash + Clone> Graph<A> {
     /// Create a new empty graph.
     #[inline]
     pub fn new() -> Self {
-        Self(ListOrderedMultimap::new())
+        Self {
+            outgoing: ListOrderedMultimap::new(),
+            incoming: ListOrderedMultimap::new(),
+        }
     }
 
-    /// Adds an edge to the graph.
+    /// Adds an edge of the given kind to the graph.
     #[inline]
-    pub fn add_edge(&mut self, a: A, b: A) {
-        self.0.append(a, b);
+    pub fn add_edge(&mut self, a: A, b: A, kind: EdgeKind) {
+        self.incoming.append(b.clone(), Edge::new(a.clone(), kind));
+        self.outgoing.append(a, Edge::new(b, kind));
     }
 
     /// Checks whether the graph contains a vertex.
     #[inline]
     pub fn has_vertex(&self, a: A) -> bool {
-        self.0.contains_key(&a)
+        self.outgoing.contains_key(&a) || self.incoming.contains_key(&a)
     }
 
     /// Returns a [Star] representing the vertex and its edges.
     #[inline]
     pub fn get_star(&self, vertex: A) -> Star<'_, A> {
         Star {
-            edges: self.0.get_all(&vertex),
+            edges: self.outgoing.get_all(&vertex),
             vertex,
         }
     }
+
+    /// Returns a [Star] representing the vertex and its incoming edges. The edges of the star
+    /// are reversed, so they point at the vertices they come from.
+    #[inline]
+    pub fn get_reverse_star(&self, vertex: A) -> Star<'_, A> {
+        Star {
+            edges: self.incoming.get_all(&vertex),
+            vertex,
+        }
+    }
+
+    /// Computes the number of edges on the shortest path from the root to each vertex that can
+    /// be reached from it.
+    pub fn depths(&self, root: A) -> HashMap<A, usize> {
+        let mut depths = HashMap::new();
+        depths.insert(root.clone(), 0);
+        let mut queue = VecDeque::from([(root, 0)]);
+        while let Some((vertex, depth)) = queue.pop_front() {
+            for edge in self.outgoing.get_all(&vertex) {
+                if let Entry::Vacant(entry) = depths.entry(edge.target.clone()) {
+                    entry.insert(depth + 1);
+                    queue.push_back((edge.target.clone(), depth + 1));
+                }
+            }
+        }
+        depths
+    }
+}
+
+/// A directed edge of a graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge<A> {
+    target: A,
+    kind: EdgeKind,
+}
+
+impl<A> Edge<A> {
+    /// Creates a new edge.
+    #[inline]
+    pub fn new(target: A, kind: EdgeKind) -> Self {
+        Self { target, kind }
+    }
+
+    /// Returns the vertex the edge points to.
+    #[inline]
+    pub fn target(&self) -> &A {
+        &self.target
+    }
+
+    /// Returns the kind of the edge.
+    #[inline]
+    pub fn kind(&self) -> EdgeKind {
+        self.kind
+    }
+}
+
+/// The kind of a transfer of control between two functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// A direct call.
+    Call,
+    /// A jump to another function in tail position.
+    TailJump,
+    /// A call or a jump through memory, the target is resolved when the memory slot holds
+    /// an address of a function.
+    Indirect,
+    /// A call or a jump to an imported function.
+    Import,
 }
 
 /// A vertex and its edges.
 #[derive(Debug)]
 pub struct Star<'graph, A> {
     vertex: A,
-    edges: EntryValues<'graph, A, A>,
+    edges: EntryValues<'graph, A, Edge<A>>,
 }
 
 impl<'graph, A> Star<'graph, A> {
@@ -50,9 +129,75 @@ impl<'graph, A> Star<'graph, A> {
         &self.vertex
     }
 
-    /// Returns an iterator over the edges.
+    /// Returns an iterator over the targets of the edges.
     #[inline]
     pub fn edges(&self) -> impl ExactSizeIterator<Item = &'graph A> + Clone {
+        self.edges.clone().map(Edge::target)
+    }
+
+    /// Returns an iterator over the edges along with their kinds.
+    #[inline]
+    pub fn edges_with_kind(&self) -> impl ExactSizeIterator<Item = &'graph Edge<A>> + Clone {
         self.edges.clone()
     }
+
+    /// Returns an iterator over the targets of the edges of the given kind.
+    #[inline]
+    pub fn edges_of_kind(&self, kind: EdgeKind) -> impl Iterator<Item = &'graph A> + Clone {
+        self.edges
+            .clone()
+            .filter(move |edge| edge.kind == kind)
+            .map(Edge::target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reverse_star() {
+        let mut graph = Graph::new();
+        graph.add_edge(1, 3, EdgeKind::Call);
+        graph.add_edge(2, 3, EdgeKind::TailJump);
+        graph.add_edge(3, 4, EdgeKind::Call);
+
+        let star = graph.get_reverse_star(3);
+        assert_eq!(
+            star.edges_with_kind().copied().collect::<Vec<_>>(),
+            &[Edge::new(1, EdgeKind::Call), Edge::new(2, EdgeKind::TailJump)]
+        );
+        assert_eq!(graph.get_reverse_star(1).edges().len(), 0);
+        assert!(graph.has_vertex(4));
+        assert!(!graph.has_vertex(5));
+        assert_eq!(graph.get_star(3).edges().copied().collect::<Vec<_>>(), &[4]);
+    }
+
+    #[test]
+    fn test_edges_of_kind() {
+        let mut graph = Graph::new();
+        graph.add_edge(1, 2, EdgeKind::Call);
+        graph.add_edge(1, 3, EdgeKind::Import);
+        graph.add_edge(1, 4, EdgeKind::Call);
+
+        let star = graph.get_star(1);
+        assert_eq!(star.edges_of_kind(EdgeKind::Call).copied().collect::<Vec<_>>(), &[2, 4]);
+        assert_eq!(star.edges_of_kind(EdgeKind::Import).copied().collect::<Vec<_>>(), &[3]);
+        assert_eq!(star.edges_of_kind(EdgeKind::TailJump).count(), 0);
+    }
+
+    #[test]
+    fn test_depths() {
+        let mut graph = Graph::new();
+        graph.add_edge(1, 2, EdgeKind::Call);
+        graph.add_edge(1, 3, EdgeKind::Call);
+        graph.add_edge(2, 4, EdgeKind::Call);
+        graph.add_edge(3, 4, EdgeKind::Call);
+        graph.add_edge(4, 1, EdgeKind::TailJump);
+        graph.add_edge(5, 1, EdgeKind::Call);
+
+        let mut depths: Vec<_> = graph.depths(1).into_iter().collect();
+        depths.sort_unstable();
+        assert_eq!(depths, &[(1, 0), (2, 1), (3, 1), (4, 2)]);
+    }
 }
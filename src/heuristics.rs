@@ -1,33 +1,197 @@
+use std::fmt;
+
 use bumpalo::collections::{CollectIn, Vec as BumpVec};
 use bumpalo::Bump;
+use hashbrown::HashMap;
+use iced_x86::Mnemonic;
 
-use crate::levenshtein::{levenshtein_matrix, LevenshteinMatrix};
+use crate::graph::{Edge, EdgeKind};
+use crate::levenshtein::{levenshtein_matrix, weighted_levenshtein_matrix, LevenshteinMatrix};
 use crate::match_star::MatchContext;
-use crate::object::CodeMetadata;
+use crate::object::{CodeMetadata, FunctionMetadata, Shape};
 
-/// A macro for creating a heuristic composed of multiple heuristics.
+/// A macro for creating a heuristic composed of multiple heuristics. Each heuristic can be
+/// given a weight with `=>`, for example `heuristics![RelativeCodeSize => 2.0, CallOrder]`.
+/// Heuristics without a weight have a weight of 1.
 #[macro_export]
 macro_rules! heuristics {
+    ($expr:expr => $weight:expr $(,)?) => {
+        $crate::heuristics::Weighted::new($expr, $weight)
+    };
     ($expr:expr $(,)?) => {
         $expr
     };
+    ($expr:expr => $weight:expr, $($trail:tt)*) => {
+        $crate::heuristics::Combined::new($crate::heuristics::Weighted::new($expr, $weight), heuristics!($($trail)*))
+    };
     ($expr:expr, $($trail:tt)*) => {
         $crate::heuristics::Combined::new($expr, heuristics!($($trail)*))
     };
 }
 
-/// A heuristic for computing the distance between edges.
+/// The cost of inserting or deleting an edge when aligning edges of weighted heuristics.
+/// Substitutions cost a fraction of it proportional to the weight of the disagreeing heuristics.
+const WEIGHT_SCALE: usize = 1000;
+
+/// The labels of both sequences of edges.
+pub type Labels<'bump> = (BumpVec<'bump, usize>, BumpVec<'bump, usize>);
+
+/// A heuristic for computing the distance between edges. The trait is object safe, so
+/// heuristics can be chosen at runtime with [`by_name`] and [`parse_list`].
 pub trait EdgeDistanceHeuristic {
+    /// Assigns a label to each edge of both sequences, edges with equal labels are considered
+    /// equivalent.
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump>;
+
+    /// Computes a [`LevenshteinMatrix`] between the labels of both sequences of edges.
     fn label<'bump>(
         &self,
-        lhs: impl IntoIterator<Item = u64> + Clone,
-        rhs: impl IntoIterator<Item = u64> + Clone,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
         ctx: MatchContext<'_>,
         bump: &'bump Bump,
-    ) -> LevenshteinMatrix<'bump>;
+    ) -> LevenshteinMatrix<'bump> {
+        let (labels_l, labels_r) = self.labels(lhs, rhs, ctx, bump);
+        levenshtein_matrix(&labels_l, &labels_r, bump)
+    }
+
+    /// Collects the labels of each heuristic this heuristic is made of along with their weights,
+    /// which are multiplied by the given weight.
+    fn weighted_labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+        weight: f64,
+        out: &mut Vec<(f64, Labels<'bump>)>,
+    ) {
+        out.push((weight, self.labels(lhs, rhs, ctx, bump)));
+    }
 }
 
-/// A heuristic that combines two heuristics.
+impl<H: EdgeDistanceHeuristic + ?Sized> EdgeDistanceHeuristic for Box<H> {
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        (**self).labels(lhs, rhs, ctx, bump)
+    }
+
+    fn label<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> LevenshteinMatrix<'bump> {
+        (**self).label(lhs, rhs, ctx, bump)
+    }
+
+    fn weighted_labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+        weight: f64,
+        out: &mut Vec<(f64, Labels<'bump>)>,
+    ) {
+        (**self).weighted_labels(lhs, rhs, ctx, bump, weight, out);
+    }
+}
+
+/// The names of the heuristics that can be created with [`by_name`].
+pub const NAMES: &[&str] = &[
+    "code-size",
+    "call-order",
+    "cfg-shape",
+    "mnemonic-hist",
+    "graph-position",
+    "constants",
+];
+
+/// Creates the heuristic with the given name.
+pub fn by_name(name: &str) -> Option<Box<dyn EdgeDistanceHeuristic>> {
+    match name {
+        "code-size" => Some(Box::new(RelativeCodeSize)),
+        "call-order" => Some(Box::new(CallOrder)),
+        "cfg-shape" => Some(Box::new(ControlFlowShape)),
+        "mnemonic-hist" => Some(Box::new(MnemonicHistogram)),
+        "graph-position" => Some(Box::new(GraphPosition)),
+        "constants" => Some(Box::new(SharedConstants)),
+        _ => None,
+    }
+}
+
+/// Parses a comma-separated list of heuristic names, each optionally followed by `=` and
+/// a weight, for example `code-size=2,call-order`. Returns a heuristic combining them.
+pub fn parse_list(list: &str) -> Result<Box<dyn EdgeDistanceHeuristic>, ParseError> {
+    if list.trim().is_empty() {
+        return Err(ParseError::Empty);
+    }
+    let heuristics = list
+        .split(',')
+        .map(|entry| {
+            let (name, weight) = match entry.split_once('=') {
+                Some((name, weight)) => (name.trim(), Some(weight.trim())),
+                None => (entry.trim(), None),
+            };
+            let heuristic = by_name(name).ok_or_else(|| ParseError::UnknownHeuristic(name.into()))?;
+            match weight {
+                Some(weight) => {
+                    let weight: f64 = weight
+                        .parse()
+                        .ok()
+                        .filter(|weight: &f64| weight.is_finite() && *weight >= 0.)
+                        .ok_or_else(|| ParseError::InvalidWeight(weight.into()))?;
+                    Ok(Box::new(Weighted::new(heuristic, weight)) as Box<dyn EdgeDistanceHeuristic>)
+                }
+                None => Ok(heuristic),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    heuristics
+        .into_iter()
+        .rev()
+        .reduce(|tail, head| Box::new(Combined::new(head, tail)))
+        .ok_or(ParseError::Empty)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownHeuristic(String),
+    InvalidWeight(String),
+    Empty,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownHeuristic(name) => {
+                write!(f, "unknown heuristic: {}, expected one of: {}", name, NAMES.join(", "))
+            }
+            ParseError::InvalidWeight(weight) => write!(f, "invalid heuristic weight: {}", weight),
+            ParseError::Empty => write!(f, "no heuristics given"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A heuristic that combines two heuristics. The edges are aligned once using the evidence of
+/// all heuristics, the cost of pairing two edges is the total weight of the heuristics that
+/// label them differently.
 #[derive(Debug)]
 pub struct Combined<H, T>(H, T);
 
@@ -39,30 +203,184 @@ impl<H, T> Combined<H, T> {
 }
 
 impl<H: EdgeDistanceHeuristic, T: EdgeDistanceHeuristic> EdgeDistanceHeuristic for Combined<H, T> {
+    /// Labels edges so that edges are equivalent only if all heuristics consider them equivalent.
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        let mut labels = vec![];
+        self.weighted_labels(lhs, rhs, ctx, bump, 1., &mut labels);
+
+        let mut ids: HashMap<Vec<usize>, usize> = HashMap::new();
+        let mut joint = |side: &dyn for<'a> Fn(&'a Labels<'bump>) -> &'a [usize]| -> BumpVec<'bump, usize> {
+            let len = labels.first().map_or(0, |(_, labels)| side(labels).len());
+            (0..len)
+                .map(|i| {
+                    let next = ids.len();
+                    let key = labels.iter().map(|(_, labels)| side(labels)[i]).collect();
+                    *ids.entry(key).or_insert(next)
+                })
+                .collect_in(bump)
+        };
+        let labels_l = joint(&|(l, _)| l);
+        let labels_r = joint(&|(_, r)| r);
+        (labels_l, labels_r)
+    }
+
     fn label<'bump>(
         &self,
-        lhs: impl IntoIterator<Item = u64> + Clone,
-        rhs: impl IntoIterator<Item = u64> + Clone,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
         ctx: MatchContext<'_>,
         bump: &'bump Bump,
     ) -> LevenshteinMatrix<'bump> {
-        let mat1 = self.0.label(lhs.clone(), rhs.clone(), ctx, bump);
-        let mat2 = self.1.label(lhs, rhs, ctx, bump);
+        let mut labels = vec![];
+        self.weighted_labels(lhs, rhs, ctx, bump, 1., &mut labels);
 
-        if mat1.distance() <= mat2.distance() {
-            mat1
-        } else {
-            mat2
+        let total: f64 = labels.iter().map(|(weight, _)| weight).sum();
+        let len_l = labels.first().map_or(0, |(_, (l, _))| l.len());
+        let len_r = labels.first().map_or(0, |(_, (_, r))| r.len());
+        let substitution_cost = |i: usize, j: usize| {
+            let mismatch: f64 = labels
+                .iter()
+                .filter(|(_, (l, r))| l[i] != r[j])
+                .map(|(weight, _)| weight)
+                .sum();
+            if total > 0. {
+                (mismatch / total * WEIGHT_SCALE as f64).round() as usize
+            } else {
+                0
+            }
+        };
+        weighted_levenshtein_matrix(len_l, len_r, WEIGHT_SCALE, substitution_cost, bump)
+    }
+
+    fn weighted_labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+        weight: f64,
+        out: &mut Vec<(f64, Labels<'bump>)>,
+    ) {
+        self.0.weighted_labels(lhs, rhs, ctx, bump, weight, out);
+        self.1.weighted_labels(lhs, rhs, ctx, bump, weight, out);
+    }
+}
+
+/// A heuristic with a weight, which determines how much it counts when combined with other
+/// heuristics. The weight should be non-negative.
+#[derive(Debug)]
+pub struct Weighted<H> {
+    inner: H,
+    weight: f64,
+}
+
+impl<H> Weighted<H> {
+    /// Creates a new heuristic with the given weight.
+    pub fn new(inner: H, weight: f64) -> Self {
+        Self { inner, weight }
+    }
+}
+
+impl<H: EdgeDistanceHeuristic> EdgeDistanceHeuristic for Weighted<H> {
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        self.inner.labels(lhs, rhs, ctx, bump)
+    }
+
+    fn label<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> LevenshteinMatrix<'bump> {
+        self.inner.label(lhs, rhs, ctx, bump)
+    }
+
+    fn weighted_labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+        weight: f64,
+        out: &mut Vec<(f64, Labels<'bump>)>,
+    ) {
+        self.inner
+            .weighted_labels(lhs, rhs, ctx, bump, weight * self.weight, out);
+    }
+}
+
+/// A heuristic that only labels edges of the given kinds using the inner heuristic.
+/// The remaining edges are given unique labels, so they are never considered equivalent.
+#[derive(Debug)]
+pub struct FilterKinds<H> {
+    inner: H,
+    kinds: Vec<EdgeKind>,
+}
+
+impl<H> FilterKinds<H> {
+    /// Creates a new heuristic that applies the inner heuristic to the edges of the given kinds.
+    pub fn new(inner: H, kinds: impl IntoIterator<Item = EdgeKind>) -> Self {
+        Self {
+            inner,
+            kinds: kinds.into_iter().collect(),
         }
     }
 }
 
+impl<H: EdgeDistanceHeuristic> EdgeDistanceHeuristic for FilterKinds<H> {
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        let is_kept = |edge: &&Edge<u64>| self.kinds.contains(&edge.kind());
+        let filtered_l: BumpVec<'bump, _> = lhs.iter().filter(is_kept).copied().collect_in(bump);
+        let filtered_r: BumpVec<'bump, _> = rhs.iter().filter(is_kept).copied().collect_in(bump);
+
+        let (kept_l, kept_r) = self.inner.labels(&filtered_l, &filtered_r, ctx, bump);
+        let mut counter = kept_l.iter().chain(&kept_r).max().map_or(0, |&max| max + 1);
+
+        let mut merge = |edges: &[Edge<u64>], kept: BumpVec<'bump, usize>| {
+            let mut kept = kept.into_iter();
+            edges
+                .iter()
+                .map(|edge| {
+                    if is_kept(&edge) {
+                        kept.next().unwrap()
+                    } else {
+                        counter += 1;
+                        counter - 1
+                    }
+                })
+                .collect_in(bump)
+        };
+        let labels_l = merge(lhs, kept_l);
+        let labels_r = merge(rhs, kept_r);
+        (labels_l, labels_r)
+    }
+}
+
 /// A heuristic that labels edges based on the order of calls.
 #[derive(Debug)]
 pub struct CallOrder;
 
 impl CallOrder {
-    fn labels<'bump>(slice: impl IntoIterator<Item = u64> + Clone, bump: &'bump Bump) -> BumpVec<'bump, usize> {
+    fn ordinals<'bump>(slice: impl IntoIterator<Item = u64> + Clone, bump: &'bump Bump) -> BumpVec<'bump, usize> {
         let mut indices: BumpVec<'bump, _> = slice
             .clone()
             .into_iter()
@@ -87,14 +405,19 @@ impl CallOrder {
 }
 
 impl EdgeDistanceHeuristic for CallOrder {
-    fn label<'bump>(
+    fn labels<'bump>(
         &self,
-        lhs: impl IntoIterator<Item = u64> + Clone,
-        rhs: impl IntoIterator<Item = u64> + Clone,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
         _ctx: MatchContext<'_>,
         bump: &'bump Bump,
-    ) -> LevenshteinMatrix<'bump> {
-        levenshtein_matrix(&Self::labels(lhs, bump), &Self::labels(rhs, bump), bump)
+    ) -> Labels<'bump> {
+        let lhs: BumpVec<'bump, _> = lhs.iter().map(|edge| *edge.target()).collect_in(bump);
+        let rhs: BumpVec<'bump, _> = rhs.iter().map(|edge| *edge.target()).collect_in(bump);
+        (
+            Self::ordinals(lhs.iter().copied(), bump),
+            Self::ordinals(rhs.iter().copied(), bump),
+        )
     }
 }
 
@@ -102,22 +425,18 @@ impl EdgeDistanceHeuristic for CallOrder {
 #[derive(Debug)]
 pub struct RelativeCodeSize;
 
-impl RelativeCodeSize {
+impl EdgeDistanceHeuristic for RelativeCodeSize {
     fn labels<'bump>(
         &self,
-        lhs: impl IntoIterator<Item = u64>,
-        rhs: impl IntoIterator<Item = u64>,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
         ctx: MatchContext<'_>,
         bump: &'bump Bump,
-    ) -> (BumpVec<'bump, usize>, BumpVec<'bump, usize>) {
-        fn weights<'bump>(
-            it: impl IntoIterator<Item = u64>,
-            ctx: &CodeMetadata,
-            bump: &'bump Bump,
-        ) -> BumpVec<'bump, (usize, f64)> {
-            let lens: BumpVec<'bump, _> = it
-                .into_iter()
-                .map(|addr| ctx.get_function(addr).unwrap().opcodes().len())
+    ) -> Labels<'bump> {
+        fn weights<'bump>(edges: &[Edge<u64>], ctx: &CodeMetadata, bump: &'bump Bump) -> BumpVec<'bump, (usize, f64)> {
+            let lens: BumpVec<'bump, _> = edges
+                .iter()
+                .map(|edge| ctx.get_function(*edge.target()).unwrap().opcodes().len())
                 .collect_in(bump);
 
             let Some(&max_len) = lens.iter().max() else {
@@ -163,27 +482,242 @@ impl RelativeCodeSize {
     }
 }
 
-impl EdgeDistanceHeuristic for RelativeCodeSize {
-    fn label<'bump>(
+/// A heuristic that labels edges based on the mnemonic frequencies of their targets. Targets are
+/// paired greedily, starting from the pair with the highest cosine similarity of mnemonic
+/// histograms.
+#[derive(Debug)]
+pub struct MnemonicHistogram;
+
+impl MnemonicHistogram {
+    fn histogram<'bump>(opcodes: &[Mnemonic], bump: &'bump Bump) -> BumpVec<'bump, (Mnemonic, usize)> {
+        let mut sorted: BumpVec<'bump, _> = opcodes.iter().copied().collect_in(bump);
+        sorted.sort_unstable();
+        let mut histogram: BumpVec<'bump, (Mnemonic, usize)> = BumpVec::new_in(bump);
+        for opcode in sorted {
+            match histogram.last_mut() {
+                Some((last, count)) if *last == opcode => *count += 1,
+                _ => histogram.push((opcode, 1)),
+            }
+        }
+        histogram
+    }
+
+    fn cosine_similarity(lhs: &[(Mnemonic, usize)], rhs: &[(Mnemonic, usize)]) -> f64 {
+        let norm = |histogram: &[(Mnemonic, usize)]| {
+            histogram
+                .iter()
+                .map(|&(_, count)| (count * count) as f64)
+                .sum::<f64>()
+                .sqrt()
+        };
+        let (norm_l, norm_r) = (norm(lhs), norm(rhs));
+        if norm_l == 0. || norm_r == 0. {
+            return 0.;
+        }
+        let dot: usize = lhs
+            .iter()
+            .filter_map(|&(opcode, count)| {
+                let i = rhs.binary_search_by_key(&opcode, |&(opcode, _)| opcode).ok()?;
+                Some(count * rhs[i].1)
+            })
+            .sum();
+        dot as f64 / (norm_l * norm_r)
+    }
+}
+
+impl EdgeDistanceHeuristic for MnemonicHistogram {
+    fn labels<'bump>(
         &self,
-        lhs: impl IntoIterator<Item = u64> + Clone,
-        rhs: impl IntoIterator<Item = u64> + Clone,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
         ctx: MatchContext<'_>,
         bump: &'bump Bump,
-    ) -> LevenshteinMatrix<'bump> {
-        let (labels_l, labels_r) = self.labels(lhs, rhs, ctx, bump);
-        levenshtein_matrix(&labels_l, &labels_r, bump)
+    ) -> Labels<'bump> {
+        let histogram = |ctx: &CodeMetadata, addr: u64| {
+            let opcodes = ctx.get_function(addr).map_or(&[][..], FunctionMetadata::opcodes);
+            Self::histogram(opcodes, bump)
+        };
+        pair_by_similarity(lhs, rhs, ctx, histogram, |l, r| Self::cosine_similarity(l, r), bump)
+    }
+}
+
+/// A heuristic that labels edges based on the constants shared by their targets, such as magic
+/// numbers, hash seeds and error codes, which are rarely changed by recompilation. Targets are
+/// paired greedily, starting from the pair with the highest Jaccard similarity of constants.
+#[derive(Debug)]
+pub struct SharedConstants;
+
+impl SharedConstants {
+    fn jaccard_similarity(lhs: &[u64], rhs: &[u64]) -> f64 {
+        let shared = lhs.iter().filter(|value| rhs.binary_search(value).is_ok()).count();
+        let total = lhs.len() + rhs.len() - shared;
+        if total == 0 {
+            0.
+        } else {
+            shared as f64 / total as f64
+        }
+    }
+}
+
+impl EdgeDistanceHeuristic for SharedConstants {
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        pair_by_similarity(
+            lhs,
+            rhs,
+            ctx,
+            |ctx, addr| ctx.get_function(addr).map_or(&[][..], FunctionMetadata::constants),
+            |l, r| Self::jaccard_similarity(l, r),
+            bump,
+        )
+    }
+}
+
+/// Labels edges by pairing their targets greedily, starting from the pair whose features are
+/// the most similar. Edges to the same target share a label, edges to targets without
+/// a counterpart of a positive similarity are given unique labels.
+fn pair_by_similarity<'a, 'bump, F>(
+    lhs: &[Edge<u64>],
+    rhs: &[Edge<u64>],
+    ctx: MatchContext<'a>,
+    features: impl Fn(&'a CodeMetadata, u64) -> F,
+    similarity: impl Fn(&F, &F) -> f64,
+    bump: &'bump Bump,
+) -> Labels<'bump> {
+    let targets = |edges: &[Edge<u64>]| {
+        let mut targets: BumpVec<'bump, u64> = edges.iter().map(|edge| *edge.target()).collect_in(bump);
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    };
+    let (targets_l, targets_r) = (targets(lhs), targets(rhs));
+    let features_l: BumpVec<'_, F> = targets_l
+        .iter()
+        .map(|&addr| features(ctx.lhs_metadata(), addr))
+        .collect_in(bump);
+    let features_r: BumpVec<'_, F> = targets_r
+        .iter()
+        .map(|&addr| features(ctx.rhs_metadata(), addr))
+        .collect_in(bump);
+
+    let mut pairs: BumpVec<'bump, (f64, usize, usize)> = BumpVec::new_in(bump);
+    for (i, lhs) in features_l.iter().enumerate() {
+        for (j, rhs) in features_r.iter().enumerate() {
+            let similarity = similarity(lhs, rhs);
+            if similarity > 0. {
+                pairs.push((similarity, i, j));
+            }
+        }
+    }
+    pairs.sort_by(|(a, i, j), (b, k, l)| b.total_cmp(a).then((i, j).cmp(&(k, l))));
+
+    // the left-hand side targets are labeled with their indices, the unpaired right-hand
+    // side targets are labeled after them
+    let mut paired_l = bumpalo::vec![in bump; false; targets_l.len()];
+    let mut labels_r = bumpalo::vec![in bump; usize::MAX; targets_r.len()];
+    for (_, i, j) in pairs {
+        if !paired_l[i] && labels_r[j] == usize::MAX {
+            paired_l[i] = true;
+            labels_r[j] = i;
+        }
+    }
+    let unpaired = labels_r.iter_mut().filter(|label| **label == usize::MAX);
+    for (counter, label) in (targets_l.len()..).zip(unpaired) {
+        *label = counter;
+    }
+
+    let index = |targets: &[u64], edge: &Edge<u64>| targets.binary_search(edge.target()).unwrap();
+    (
+        lhs.iter().map(|edge| index(&targets_l, edge)).collect_in(bump),
+        rhs.iter()
+            .map(|edge| labels_r[index(&targets_r, edge)])
+            .collect_in(bump),
+    )
+}
+
+/// A heuristic that labels edges based on the shape of the control flow graphs of their
+/// targets. Edges to functions without control flow graphs are given unique labels.
+#[derive(Debug)]
+pub struct ControlFlowShape;
+
+impl EdgeDistanceHeuristic for ControlFlowShape {
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        let mut shapes: HashMap<Shape, usize> = HashMap::new();
+        let mut counter = 0;
+
+        let mut label = |edges: &[Edge<u64>], ctx: &CodeMetadata| -> BumpVec<'bump, usize> {
+            edges
+                .iter()
+                .map(|edge| {
+                    let next = counter;
+                    counter += 1;
+                    match ctx.cfg(*edge.target()) {
+                        Some(cfg) => *shapes.entry(cfg.shape()).or_insert(next),
+                        None => next,
+                    }
+                })
+                .collect_in(bump)
+        };
+        let labels_l = label(lhs, ctx.lhs_metadata());
+        let labels_r = label(rhs, ctx.rhs_metadata());
+        (labels_l, labels_r)
+    }
+}
+
+/// A heuristic that labels edges based on the position of their targets in the call graph,
+/// given by their number of callees, their number of callers and their depth from the
+/// entrypoint. It distinguishes thin wrappers, which all have a similar size.
+#[derive(Debug)]
+pub struct GraphPosition;
+
+impl EdgeDistanceHeuristic for GraphPosition {
+    fn labels<'bump>(
+        &self,
+        lhs: &[Edge<u64>],
+        rhs: &[Edge<u64>],
+        ctx: MatchContext<'_>,
+        bump: &'bump Bump,
+    ) -> Labels<'bump> {
+        let mut positions: HashMap<(usize, usize, Option<usize>), usize> = HashMap::new();
+
+        let mut label = |edges: &[Edge<u64>], ctx: &CodeMetadata| -> BumpVec<'bump, usize> {
+            edges
+                .iter()
+                .map(|edge| {
+                    let addr = *edge.target();
+                    let position = (
+                        ctx.call_graph().get_star(addr).edges().len(),
+                        ctx.call_graph().get_reverse_star(addr).edges().len(),
+                        ctx.get_function(addr).and_then(FunctionMetadata::depth),
+                    );
+                    let next = positions.len();
+                    *positions.entry(position).or_insert(next)
+                })
+                .collect_in(bump)
+        };
+        let labels_l = label(lhs, ctx.lhs_metadata());
+        let labels_r = label(rhs, ctx.rhs_metadata());
+        (labels_l, labels_r)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use iced_x86::Mnemonic;
     use test_case::test_case;
 
     use super::*;
     use crate::graph::Graph;
-    use crate::object::FunctionMetadata;
 
     fn test_obj1() -> CodeMetadata {
         let func1 = FunctionMetadata::new(vec![Mnemonic::Call, Mnemonic::Mov]);
@@ -207,6 +741,10 @@ mod test {
         }
     }
 
+    fn calls(addrs: &[u64]) -> Vec<Edge<u64>> {
+        addrs.iter().map(|&addr| Edge::new(addr, EdgeKind::Call)).collect()
+    }
+
     #[test_case(&[512, 513, 514], &[1024, 1025, 1026], &[0, 1, 2], &[0, 1, 2])]
     #[test_case(&[512, 513, 514], &[1025, 1026], &[4, 1, 2], &[1, 2])]
     #[test_case(&[514], &[1024, 1025, 1026], &[0], &[1, 2, 0])]
@@ -216,8 +754,24 @@ mod test {
     fn test_label_code_size(lhs: &[u64], rhs: &[u64], lhs_labels: &[usize], rhs_labels: &[usize]) {
         let bump = Bump::new();
         let (l, r) = RelativeCodeSize.labels(
-            lhs.iter().copied(),
-            rhs.iter().copied(),
+            &calls(lhs),
+            &calls(rhs),
+            MatchContext::new(&test_obj1(), &test_obj2()),
+            &bump,
+        );
+        assert_eq!(l, lhs_labels);
+        assert_eq!(r, rhs_labels);
+    }
+
+    #[test_case(&[512, 513, 514], &[1025, 1024, 1026], &[0, 1, 2], &[1, 0, 3])]
+    #[test_case(&[513, 512, 513], &[1024, 1025], &[1, 0, 1], &[0, 1])]
+    #[test_case(&[512], &[1025, 1026], &[0], &[0, 1])]
+    #[test_case(&[514], &[1026], &[0], &[1])]
+    fn test_label_mnemonic_histogram(lhs: &[u64], rhs: &[u64], lhs_labels: &[usize], rhs_labels: &[usize]) {
+        let bump = Bump::new();
+        let (l, r) = MnemonicHistogram.labels(
+            &calls(lhs),
+            &calls(rhs),
             MatchContext::new(&test_obj1(), &test_obj2()),
             &bump,
         );
@@ -225,12 +779,131 @@ mod test {
         assert_eq!(r, rhs_labels);
     }
 
+    #[test]
+    fn test_label_graph_position() {
+        let metadata = |base: u64, wrappers: &[u64]| {
+            let mut call_graph = Graph::new();
+            for &wrapper in wrappers {
+                call_graph.add_edge(base, base + wrapper, EdgeKind::Call);
+                call_graph.add_edge(base + wrapper, base + 0x10 + wrapper, EdgeKind::Import);
+            }
+            call_graph.add_edge(base + 1, base + 3, EdgeKind::Call);
+            let functions = (0..0x20)
+                .map(|offset| (base + offset, FunctionMetadata::new(vec![Mnemonic::Jmp])))
+                .collect();
+            let mut metadata = CodeMetadata { call_graph, functions };
+            metadata.compute_depths(base);
+            metadata
+        };
+        let lhs = metadata(0x100, &[1, 2, 3]);
+        let rhs = metadata(0x200, &[2, 1, 3]);
+        let edges = |metadata: &CodeMetadata, base: u64| {
+            metadata
+                .call_graph()
+                .get_star(base)
+                .edges_with_kind()
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        let bump = Bump::new();
+        let (l, r) = GraphPosition.labels(
+            &edges(&lhs, 0x100),
+            &edges(&rhs, 0x200),
+            MatchContext::new(&lhs, &rhs),
+            &bump,
+        );
+        assert_eq!(l, &[0, 1, 2]);
+        assert_eq!(r, &[1, 0, 2]);
+    }
+
+    #[test_case(&[0x1000, 0xEDB8_8320], &[0x0100_0193, 0xEDB8_8320], 1. / 3.)]
+    #[test_case(&[0x1000, 0x2000], &[0x1000, 0x2000], 1.)]
+    #[test_case(&[0x1000], &[0x2000], 0.)]
+    #[test_case(&[], &[], 0.)]
+    fn test_jaccard_similarity(lhs: &[u64], rhs: &[u64], expected: f64) {
+        assert_eq!(SharedConstants::jaccard_similarity(lhs, rhs), expected);
+    }
+
     #[test_case(&[512, 513, 514], &[0, 1, 2])]
     #[test_case(&[512, 513, 513, 514, 513], &[0, 1, 1, 2, 1])]
     #[test_case(&[512, 513, 514, 513, 514, 515, 513, 514, 512], &[0, 1, 2, 1, 2, 3, 1, 2, 0])]
     fn test_label_call_order(edges: &[u64], labels: &[usize]) {
         let bump = Bump::new();
-        let res = CallOrder::labels(edges.iter().copied(), &bump);
+        let res = CallOrder::ordinals(edges.iter().copied(), &bump);
         assert_eq!(res, labels);
     }
+
+    struct Fixed(&'static [usize], &'static [usize]);
+
+    impl EdgeDistanceHeuristic for Fixed {
+        fn labels<'bump>(
+            &self,
+            _lhs: &[Edge<u64>],
+            _rhs: &[Edge<u64>],
+            _ctx: MatchContext<'_>,
+            bump: &'bump Bump,
+        ) -> Labels<'bump> {
+            (
+                self.0.iter().copied().collect_in(bump),
+                self.1.iter().copied().collect_in(bump),
+            )
+        }
+    }
+
+    #[test_case(1., 1., 1000)]
+    #[test_case(2., 1., 1334)]
+    #[test_case(1., 2., 666)]
+    #[test_case(1., 0., 2000)]
+    fn test_combined_weights(weight1: f64, weight2: f64, expected: usize) {
+        let bump = Bump::new();
+        let (lhs, rhs) = (test_obj1(), test_obj2());
+        let heuristics = heuristics![Fixed(&[0, 1], &[1, 0]) => weight1, Fixed(&[0, 1], &[0, 1]) => weight2];
+        let mat = heuristics.label(&[], &[], MatchContext::new(&lhs, &rhs), &bump);
+        assert_eq!(mat.distance(), expected);
+    }
+
+    #[test]
+    fn test_combined_labels() {
+        let bump = Bump::new();
+        let (lhs, rhs) = (test_obj1(), test_obj2());
+        let heuristics = heuristics![Fixed(&[0, 1, 1], &[1, 0]), Fixed(&[0, 1, 1], &[0, 1])];
+        let (l, r) = heuristics.labels(&[], &[], MatchContext::new(&lhs, &rhs), &bump);
+        assert_eq!(l, &[0, 1, 1]);
+        assert_eq!(r, &[2, 3]);
+    }
+
+    #[test_case(&[512, 513, 514], &[1024, 1025, 1026], &[0, 2, 1], &[0, 3, 1])]
+    #[test_case(&[514, 512], &[1026, 1025], &[0, 1], &[0, 2])]
+    fn test_label_filter_kinds(lhs: &[u64], rhs: &[u64], lhs_labels: &[usize], rhs_labels: &[usize]) {
+        let bump = Bump::new();
+        let kind = |addr: u64| match addr % 512 {
+            1 => EdgeKind::Import,
+            _ => EdgeKind::Call,
+        };
+        let (l, r) = FilterKinds::new(CallOrder, [EdgeKind::Call]).labels(
+            &lhs.iter().map(|&addr| Edge::new(addr, kind(addr))).collect::<Vec<_>>(),
+            &rhs.iter().map(|&addr| Edge::new(addr, kind(addr))).collect::<Vec<_>>(),
+            MatchContext::new(&test_obj1(), &test_obj2()),
+            &bump,
+        );
+        assert_eq!(l, lhs_labels);
+        assert_eq!(r, rhs_labels);
+    }
+
+    #[test_case("code-size,call-order", Ok(()))]
+    #[test_case("cfg-shape = 2.5, call-order", Ok(()))]
+    #[test_case("call-order,mnemonics", Err(ParseError::UnknownHeuristic("mnemonics".into())))]
+    #[test_case("code-size=-1", Err(ParseError::InvalidWeight("-1".into())))]
+    #[test_case(" ", Err(ParseError::Empty))]
+    fn test_parse_list(list: &str, expected: Result<(), ParseError>) {
+        assert_eq!(parse_list(list).map(|_| ()), expected);
+    }
+
+    #[test]
+    fn test_registry() {
+        for name in NAMES {
+            assert!(by_name(name).is_some(), "{} is not registered", name);
+        }
+    }
 }
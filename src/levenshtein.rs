@@ -52,30 +52,67 @@ where
     mat
 }
 
+/// Computes a [`LevenshteinMatrix`] for two sequences of the given lengths using custom costs.
+/// The cost of substituting elements is given by a function of their indices and must not
+/// exceed the cost of an insertion or a deletion.
+pub fn weighted_levenshtein_matrix<'a>(
+    cols: usize,
+    rows: usize,
+    indel_cost: usize,
+    substitution_cost: impl Fn(usize, usize) -> usize,
+    bump: &'a Bump,
+) -> LevenshteinMatrix<'a> {
+    let mut mat = LevenshteinMatrix::with_indel_cost(cols, rows, indel_cost, bump);
+    let mut substitutions = bumpalo::vec![in bump; 0; mat.cols * mat.rows];
+
+    for j in 0..rows {
+        for i in 0..cols {
+            let substitution_cost = substitution_cost(i, j);
+            substitutions[(j + 1) * mat.cols + i + 1] = substitution_cost;
+            let cost = (mat.get(i, j + 1) + indel_cost)
+                .min(mat.get(i + 1, j) + indel_cost)
+                .min(mat.get(i, j) + substitution_cost);
+            mat.set(i + 1, j + 1, cost);
+        }
+    }
+
+    mat.substitutions = Some(substitutions.into_bump_slice());
+    mat
+}
+
 /// A Levenshtein distance matrix.
 #[derive(Debug, Default)]
 pub struct LevenshteinMatrix<'a> {
     cols: usize,
     rows: usize,
     matrix: &'a mut [usize],
+    indel_cost: usize,
+    // substitution costs of each cell, only present when they're not uniform
+    substitutions: Option<&'a [usize]>,
 }
 
 impl<'a> LevenshteinMatrix<'a> {
     #[inline]
     pub fn new(cols: usize, rows: usize, bump: &'a Bump) -> Self {
+        Self::with_indel_cost(cols, rows, 1, bump)
+    }
+
+    fn with_indel_cost(cols: usize, rows: usize, indel_cost: usize, bump: &'a Bump) -> Self {
         let n = cols + 1;
         let m = rows + 1;
         let mut this = Self {
             cols: n,
             rows: m,
             matrix: bump.alloc_slice_fill_copy(n * m, 0),
+            indel_cost,
+            substitutions: None,
         };
 
         for i in 0..n {
-            this.set(i, 0, i);
+            this.set(i, 0, i * indel_cost);
         }
         for i in 0..m {
-            this.set(0, i, i);
+            this.set(0, i, i * indel_cost);
         }
         this
     }
@@ -147,6 +184,10 @@ impl<'a> Iterator for Edits<'a> {
             return None;
         }
 
+        if let Some(substitutions) = self.matrix.substitutions {
+            return Some(self.next_weighted(substitutions));
+        }
+
         let current = self.matrix.get(self.x, self.y);
         let x1 = self.x.checked_sub(1);
         let y1 = self.y.checked_sub(1);
@@ -172,6 +213,33 @@ impl<'a> Iterator for Edits<'a> {
     }
 }
 
+impl Edits<'_> {
+    /// Walks back along the cell that the current cost was computed from. Unlike with uniform
+    /// costs, the neighbour with the lowest cost isn't necessarily the one.
+    fn next_weighted(&mut self, substitutions: &[usize]) -> Edit {
+        let current = self.matrix.get(self.x, self.y);
+        if self.x > 0 && self.y > 0 {
+            let substitution_cost = substitutions[self.y * self.matrix.cols + self.x];
+            if self.matrix.get(self.x - 1, self.y - 1) + substitution_cost == current {
+                self.x -= 1;
+                self.y -= 1;
+                return if substitution_cost == 0 {
+                    Edit::Noop
+                } else {
+                    Edit::Substitute(self.y)
+                };
+            }
+        }
+        if self.x > 0 && self.matrix.get(self.x - 1, self.y) + self.matrix.indel_cost == current {
+            self.x -= 1;
+            Edit::Delete
+        } else {
+            self.y -= 1;
+            Edit::Insert(self.y)
+        }
+    }
+}
+
 /// An edit operation.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Edit {
@@ -228,4 +296,19 @@ mod test {
         result.edits().apply(&mut tmp, s2);
         assert_eq!(tmp, s2);
     }
+
+    #[test_case(b"kitten", b"sitting", 1, 4)]
+    #[test_case(b"kitten", b"sitting", 2, 6)]
+    #[test_case(b"Saturday", b"Sunday", 1, 5)]
+    #[test_case(b"Saturday", b"Sunday", 2, 6)]
+    fn test_weighted_levenshtein_matrix(s1: &[u8], s2: &[u8], substitution_cost: usize, expected: usize) {
+        let bump = Bump::new();
+        let cost = |i: usize, j: usize| if s1[i] == s2[j] { 0 } else { substitution_cost };
+        let result = super::weighted_levenshtein_matrix(s1.len(), s2.len(), 2, cost, &bump);
+        assert_eq!(result.distance(), expected);
+
+        let mut tmp = s1.iter().copied().collect_in(&bump);
+        result.edits().apply(&mut tmp, s2);
+        assert_eq!(tmp, s2);
+    }
 }
@@ -1,9 +1,17 @@
-pub use belief_prop::belief_prop;
-pub use object::{CodeMetadata, ObjectCode};
+pub use belief_prop::{belief_prop, belief_prop_with_rematch, Mapping, MatchDetails, Origin};
+pub use graph::{Edge, EdgeKind, Graph, Star};
+pub use match_star::MatchConfig;
+pub use object::{
+    BasicBlock, CodeMetadata, ControlFlowGraph, FunctionSymbol, Granularity, LoadOptions, ObjectCode,
+    ParseGranularityError, Shape, Token,
+};
 
 mod belief_prop;
+pub mod diff;
 mod graph;
 pub mod heuristics;
 mod levenshtein;
 mod match_star;
 mod object;
+pub mod pattern;
+pub mod seeds;
@@ -1,29 +1,130 @@
-use bumpalo::collections::Vec as BumpVec;
+use bumpalo::collections::{CollectIn, Vec as BumpVec};
 use bumpalo::Bump;
 
 use crate::graph::Star;
 use crate::heuristics::EdgeDistanceHeuristic;
 use crate::levenshtein::{levenshtein, Edit};
-use crate::object::CodeMetadata;
+use crate::object::{CodeMetadata, Granularity};
 
-const INSERT_DELETE_COST: usize = 1;
-const CONFLICT_COST: usize = 1;
+/// The cost model used to compare functions and their stars, along with the threshold above
+/// which proposed pairs aren't trusted.
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+    /// The cost of an edge of one star that isn't mapped to any edge of the other star.
+    pub insert_delete_cost: f64,
+    /// The cost of a mapping between edges that disagrees with the most frequent mapping of
+    /// its edge.
+    pub conflict_cost: f64,
+    /// The weight of the distance between the instructions of the functions.
+    pub opcode_weight: f64,
+    /// The weight of the distance between the control flow graphs of the functions.
+    pub cfg_weight: f64,
+    /// Divide the distance between the instructions by the length of the longer function and
+    /// the distance between the control flow graphs by the size of the larger graph, so that
+    /// large functions aren't ranked below small ones just because of their size.
+    pub normalize: bool,
+    /// The level of detail at which instructions are compared.
+    pub granularity: Granularity,
+    /// The maximum distance of a pair proposed by the propagation, taken as the larger of the
+    /// normalized opcode distance and the normalized import name distance. Pairs above it
+    /// aren't matched and don't propagate to their neighbours. Seeds are always accepted.
+    pub max_distance: Option<f64>,
+    /// Keep the pairs above the maximum distance as tentative matches instead of rejecting
+    /// them.
+    pub keep_tentative: bool,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            insert_delete_cost: 1.,
+            conflict_cost: 1.,
+            opcode_weight: 1.,
+            cfg_weight: 1.,
+            normalize: false,
+            granularity: Granularity::default(),
+            max_distance: None,
+            keep_tentative: false,
+        }
+    }
+}
 
 pub fn match_star(
     lhs: Star<'_, u64>,
     rhs: Star<'_, u64>,
-    heuristics: &impl EdgeDistanceHeuristic,
+    heuristics: &(impl EdgeDistanceHeuristic + ?Sized),
+    config: &MatchConfig,
     ctx: MatchContext<'_>,
     bump: &Bump,
-) -> (usize, Vec<(u64, u64)>) {
-    // The initial cost is based on the distance between the two sets of opcodes.
-    let mut cost = levenshtein(
-        ctx.lhs_metadata.get_function(*lhs.vertex()).unwrap().opcodes(),
-        ctx.rhs_metadata.get_function(*rhs.vertex()).unwrap().opcodes(),
-        bump,
-    );
+) -> StarMatch {
+    let lhs_func = ctx.lhs_metadata.get_function(*lhs.vertex()).unwrap();
+    let rhs_func = ctx.rhs_metadata.get_function(*rhs.vertex()).unwrap();
+    let normalize = |dist: usize, max_len: usize| match max_len {
+        0 => 0.,
+        _ => dist as f64 / max_len as f64,
+    };
+    // The initial cost is based on the distance between the two sequences of instructions.
+    let (opcode_dist, max_len) = match config.granularity {
+        Granularity::Mnemonic => (
+            levenshtein(lhs_func.opcodes(), rhs_func.opcodes(), bump),
+            lhs_func.opcodes().len().max(rhs_func.opcodes().len()),
+        ),
+        Granularity::Operands => (
+            levenshtein(lhs_func.tokens(), rhs_func.tokens(), bump),
+            lhs_func.tokens().len().max(rhs_func.tokens().len()),
+        ),
+    };
+    let mut code_cost = if config.normalize {
+        normalize(opcode_dist, max_len)
+    } else {
+        opcode_dist as f64
+    };
+    // Imported functions have no code, so they're compared by their names instead.
+    let mut name_dist = 0.;
+    if lhs_func.import_name().is_some() || rhs_func.import_name().is_some() {
+        let lhs_name = lhs_func.import_name().unwrap_or_default();
+        let rhs_name = rhs_func.import_name().unwrap_or_default();
+        let dist = levenshtein(lhs_name.as_bytes(), rhs_name.as_bytes(), bump);
+        name_dist = normalize(dist, lhs_name.len().max(rhs_name.len()));
+        code_cost += if config.normalize { name_dist } else { dist as f64 };
+    }
+    let mut cost = config.opcode_weight * code_cost;
+    // The structure of the functions is compared when control flow graphs are available.
+    if let (Some(lhs_cfg), Some(rhs_cfg)) = (lhs_func.cfg(), rhs_func.cfg()) {
+        let cfg_dist = if config.normalize {
+            lhs_cfg.normalized_distance(rhs_cfg)
+        } else {
+            lhs_cfg.distance(rhs_cfg) as f64
+        };
+        cost += config.cfg_weight * cfg_dist;
+    }
+
+    let (edge_cost, conflicts, mappings) = match_edges(lhs, rhs, heuristics, config, ctx, bump);
+    cost += edge_cost;
 
-    let mat = heuristics.label(lhs.edges().copied(), rhs.edges().copied(), ctx, bump);
+    StarMatch {
+        dist: cost,
+        opcode_dist: normalize(opcode_dist, max_len),
+        name_dist,
+        conflicts,
+        mappings,
+    }
+}
+
+/// Matches the edges of two stars without comparing their vertices. Returns the cost of the
+/// matching, the number of conflicting mappings and the mappings between the edges.
+pub fn match_edges(
+    lhs: Star<'_, u64>,
+    rhs: Star<'_, u64>,
+    heuristics: &(impl EdgeDistanceHeuristic + ?Sized),
+    config: &MatchConfig,
+    ctx: MatchContext<'_>,
+    bump: &Bump,
+) -> (f64, usize, Vec<(u64, u64)>) {
+    let mut cost = 0.;
+    let lhs_edges: BumpVec<'_, _> = lhs.edges_with_kind().copied().collect_in(bump);
+    let rhs_edges: BumpVec<'_, _> = rhs.edges_with_kind().copied().collect_in(bump);
+    let mat = heuristics.label(&lhs_edges, &rhs_edges, ctx, bump);
 
     // The mapping between edges is generated by iterating through an edit sequence obtained
     // from a Levenshtein matrix. Substitutions and noops correspond to simple mappings between
@@ -34,7 +135,7 @@ pub fn match_star(
             Edit::Substitute(j) => j,
             Edit::Noop if rhs.edges().len() > i => i,
             _ => {
-                cost += INSERT_DELETE_COST;
+                cost += config.insert_delete_cost;
                 continue;
             }
         };
@@ -45,9 +146,34 @@ pub fn match_star(
     mappings.sort_unstable();
     let max_mappings = find_max_within_runs(&mappings, bump);
     // The cost is increased by the number of conflicting mappings.
-    cost += count_conflicts(&mappings, &max_mappings) * CONFLICT_COST;
+    let conflicts = count_conflicts(&mappings, &max_mappings);
+    cost += conflicts as f64 * config.conflict_cost;
+
+    (cost, conflicts, max_mappings.to_vec())
+}
 
-    (cost, max_mappings.to_vec())
+/// The result of matching two stars.
+#[derive(Debug)]
+pub struct StarMatch {
+    /// The total cost of matching the stars.
+    pub dist: f64,
+    /// The distance between the opcodes of the two functions divided by the length of the longer sequence.
+    pub opcode_dist: f64,
+    /// The distance between the import names of the two functions divided by the length of the
+    /// longer name, or 0 when neither function is imported.
+    pub name_dist: f64,
+    /// The number of conflicting mappings between edges.
+    pub conflicts: usize,
+    /// The mappings between edges of the stars.
+    pub mappings: Vec<(u64, u64)>,
+}
+
+impl StarMatch {
+    /// Returns the distance between the functions themselves, between 0 and 1. Functions with
+    /// code are compared by their opcodes and imported functions by their names.
+    pub fn normalized_dist(&self) -> f64 {
+        self.opcode_dist.max(self.name_dist)
+    }
 }
 
 /// Finds the most frequent mapping for each left-hand side index.
@@ -126,8 +252,48 @@ impl<'a> MatchContext<'a> {
 #[cfg(test)]
 mod test {
     use bumpalo::Bump;
+    use iced_x86::Mnemonic;
+    use test_case::test_case;
 
     use super::*;
+    use crate::heuristics::CallOrder;
+
+    #[test_case(MatchConfig::default(), 3.; "default")]
+    #[test_case(MatchConfig { opcode_weight: 2., ..MatchConfig::default() }, 5.; "opcode weight")]
+    #[test_case(MatchConfig { insert_delete_cost: 0.5, ..MatchConfig::default() }, 2.5; "insert delete cost")]
+    #[test_case(MatchConfig { normalize: true, ..MatchConfig::default() }, 1.5; "normalized")]
+    #[test_case(MatchConfig { granularity: Granularity::Operands, normalize: true, ..MatchConfig::default() }, 1.5; "operands")]
+    fn test_match_star_config(config: MatchConfig, expected: f64) {
+        let lhs = CodeMetadata::with_calls(
+            0,
+            &[(0, 1)],
+            &[
+                (0, &[Mnemonic::Push, Mnemonic::Call, Mnemonic::Pop, Mnemonic::Ret]),
+                (1, &[Mnemonic::Ret]),
+            ],
+        );
+        let rhs = CodeMetadata::with_calls(
+            10,
+            &[(0, 1), (0, 2)],
+            &[
+                (0, &[Mnemonic::Call, Mnemonic::Call, Mnemonic::Ret]),
+                (1, &[Mnemonic::Ret]),
+                (2, &[Mnemonic::Ret]),
+            ],
+        );
+        let bump = Bump::new();
+        let ctx = MatchContext::new(&lhs, &rhs);
+        let star = match_star(
+            lhs.call_graph.get_star(0),
+            rhs.call_graph.get_star(10),
+            &CallOrder,
+            &config,
+            ctx,
+            &bump,
+        );
+        assert_eq!(star.dist, expected);
+        assert_eq!(star.opcode_dist, 0.5);
+    }
 
     #[test]
     fn test_find_max_runs() {
@@ -1,13 +1,36 @@
+use std::collections::hash_map::DefaultHasher;
 use std::error::Error as StdError;
-use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::{fmt, mem};
 
 use hashbrown::HashMap;
-use iced_x86::{Decoder, Instruction, MemorySize, Mnemonic};
-use object::{Object, ObjectSection};
+use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic, OpKind, Register};
+use object::pe::{ImageRuntimeFunctionEntry, IMAGE_DIRECTORY_ENTRY_EXCEPTION};
+use object::read::pe::{ImageNtHeaders, ImageThunkData, Import, PeFile, PeFile64};
+use object::{
+    elf, macho, pe, Architecture, LittleEndian as LE, Object, ObjectSection, ObjectSymbol, ObjectSymbolTable,
+    RelocationTarget, SectionFlags, SectionKind, SymbolKind,
+};
 
-use crate::graph::Graph;
+pub(crate) use self::cfg::CfgBuilder;
+pub use self::cfg::{BasicBlock, ControlFlowGraph, Shape};
+use self::jump_table::JumpTableTracker;
+pub use self::token::{Granularity, ParseGranularityError, Token};
+use crate::graph::{EdgeKind, Graph};
 
-const TEXT_SECTION_NAME: &str = ".text";
+mod cfg;
+mod jump_table;
+mod token;
+
+const UNW_FLAG_CHAININFO: u8 = 0x4;
+
+/// The minimum number of characters of a string referenced by code.
+const MIN_STRING_LEN: usize = 4;
+/// The maximum number of bytes scanned for the end of a string.
+const MAX_STRING_LEN: usize = 4096;
+/// The magnitude below which constants are too common to tell functions apart.
+const MIN_CONSTANT: u64 = 0x1000;
 
 const ALIGN_SEQUENCES: &[&[u8]] = &[
     &[0xCC, 0xCC],
@@ -18,37 +41,302 @@ const ALIGN_SEQUENCES: &[&[u8]] = &[
     &[0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
 ];
 
-/// Represents the text section of an object file.
+/// Represents the executable sections of an object file along with the data they reference.
 #[derive(Debug)]
-pub struct ObjectCode<'file, 'data> {
-    text: object::Section<'file, 'data>,
+pub struct ObjectCode<'data> {
+    sections: Vec<MappedSection<'data>>,
+    ranges: Vec<Range<u64>>,
     entry: u64,
+    bitness: u32,
+    function_bounds: HashMap<u64, u64>,
+    symbols: Vec<FunctionSymbol<'data>>,
+    imports: HashMap<u64, Box<str>>,
 }
 
-impl<'file, 'data> ObjectCode<'file, 'data> {
+impl<'data> ObjectCode<'data> {
+    /// Creates an object without any code that only holds the given symbols.
+    #[cfg(test)]
+    pub(crate) fn with_symbols(symbols: Vec<FunctionSymbol<'data>>) -> Self {
+        Self {
+            sections: vec![],
+            entry: 0,
+            bitness: 64,
+            function_bounds: HashMap::new(),
+            symbols,
+            imports: HashMap::new(),
+            ranges: vec![],
+        }
+    }
+
+    /// Creates an object with a code section at 0x1000 and a read-only data section at 0x2000.
+    #[cfg(test)]
+    pub(crate) fn with_sections(code: &'data [u8], data: &'data [u8]) -> Self {
+        Self {
+            sections: vec![
+                MappedSection::new(0x1000, code, true, false),
+                MappedSection::new(0x2000, data, false, true),
+            ],
+            ranges: vec![0x1000..0x1000 + code.len() as u64, 0x2000..0x2000 + data.len() as u64],
+            entry: 0x1000,
+            bitness: 64,
+            function_bounds: HashMap::new(),
+            symbols: vec![],
+            imports: HashMap::new(),
+        }
+    }
+
     /// Loads code from an object file.
-    pub fn load(file: &'file object::read::File<'data>) -> Result<Self, Error> {
-        let text = file
-            .section_by_name(TEXT_SECTION_NAME)
-            .ok_or(Error::MissingTextSection)?;
+    pub fn load(file: &object::read::File<'data>) -> Result<Self, Error> {
+        let mut sections = vec![];
+        for section in file
+            .sections()
+            .filter(|section| is_executable(section) || is_data(section))
+        {
+            let data = section.data().map_err(|err| Error::Other(err.into()))?;
+            if !data.is_empty() {
+                sections.push(MappedSection::new(
+                    section.address(),
+                    data,
+                    is_executable(&section),
+                    is_read_only(&section),
+                ));
+            }
+        }
+        if !sections.iter().any(|section| section.executable) {
+            return Err(Error::MissingCodeSection);
+        }
+        sections.sort_by_key(|section| section.address);
+        // sections without file data like .bss are part of the address space too
+        let ranges = file
+            .sections()
+            .filter(|section| section.address() != 0 && section.size() != 0)
+            .map(|section| section.address()..section.address() + section.size())
+            .collect();
+
+        let bitness = match file.architecture() {
+            Architecture::I386 => 32,
+            Architecture::X86_64 | Architecture::X86_64_X32 => 64,
+            arch => return Err(Error::UnsupportedArchitecture(arch)),
+        };
+        let function_bounds = match file {
+            // functions without exact bounds fall back to guessed ones
+            object::File::Pe64(pe) => load_exception_directory(pe).unwrap_or_default(),
+            _ => HashMap::new(),
+        };
+        // malformed import tables are common in packed files, their imports stay unresolved
+        let imports = match file {
+            object::File::Pe32(pe) => load_import_table(pe).unwrap_or_default(),
+            object::File::Pe64(pe) => load_import_table(pe).unwrap_or_default(),
+            _ => load_dynamic_imports(file),
+        };
 
         Ok(Self {
+            sections,
+            ranges,
             entry: file.entry(),
-            text,
+            bitness,
+            function_bounds,
+            symbols: vec![],
+            imports,
         })
     }
 
-    /// Returns the relative address of the entrypoint in the text section.
+    /// Loads code from an object file, additionally using its symbol tables and exports to
+    /// find function starts and sizes.
+    pub fn load_with_symbols(file: &object::read::File<'data>) -> Result<Self, Error> {
+        let mut this = Self::load(file)?;
+        this.symbols = load_function_symbols(file, &this);
+        for sym in &this.symbols {
+            if sym.size != 0 {
+                this.function_bounds
+                    .entry(sym.address)
+                    .or_insert(sym.address + sym.size);
+            }
+        }
+        Ok(this)
+    }
+
+    /// Returns the address of the entrypoint.
     pub fn entrypoint(&self) -> u64 {
-        self.entry - self.text_section_base()
+        self.entry
+    }
+
+    /// Returns the bitness of the code, either 32 or 64.
+    pub fn bitness(&self) -> u32 {
+        self.bitness
+    }
+
+    /// Returns the end address of the function starting at the given address if the object file
+    /// describes its boundaries exactly.
+    pub fn function_end(&self, addr: u64) -> Option<u64> {
+        self.function_bounds.get(&addr).copied()
+    }
+
+    /// Returns the start addresses of functions found without following calls from the
+    /// entrypoint: functions with exact bounds, symbols and targets of direct calls found by
+    /// a linear sweep of the executable sections. The addresses are sorted and unique.
+    pub fn function_starts(&self) -> Vec<u64> {
+        let mut starts: Vec<_> = self
+            .function_bounds
+            .keys()
+            .copied()
+            .chain(self.symbols.iter().map(|sym| sym.address))
+            .collect();
+        let mut instruction = Instruction::default();
+        for (address, code) in self.code_sections() {
+            let mut decoder = Decoder::with_ip(self.bitness, code, address, DecoderOptions::NONE);
+            while decoder.can_decode() {
+                decoder.decode_out(&mut instruction);
+                if instruction.mnemonic() != Mnemonic::Call || instruction.op0_kind() == OpKind::Memory {
+                    continue;
+                }
+                // thunks of imported functions are attributed to the imports when they're called
+                starts.extend(
+                    branch_target(&instruction)
+                        .filter(|&target| self.is_code(target) && self.import_thunk(target).is_none()),
+                );
+            }
+        }
+        starts.sort_unstable();
+        starts.dedup();
+        starts
+    }
+
+    /// Returns the function symbols of the object file sorted by address. The symbols are only
+    /// available when the object is loaded with [`ObjectCode::load_with_symbols`].
+    pub fn symbols(&self) -> &[FunctionSymbol<'data>] {
+        &self.symbols
+    }
+
+    /// Returns the name of the function imported through the slot (an IAT or a GOT entry) at the
+    /// given address.
+    pub fn import(&self, slot: u64) -> Option<&str> {
+        self.imports.get(&slot).map(AsRef::as_ref)
+    }
+
+    /// Returns an iterator over the import slot addresses and the names of imported functions.
+    pub fn imports(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.imports.iter().map(|(&slot, name)| (slot, name.as_ref()))
+    }
+
+    /// Returns the import slot used by the thunk at the given address, if the address points at
+    /// a thunk that jumps to an imported function, such as a PLT entry or an import stub.
+    pub fn import_thunk(&self, addr: u64) -> Option<u64> {
+        let mut decoder = Decoder::with_ip(self.bitness, self.code_at(addr)?, addr, DecoderOptions::NONE);
+        let mut instruction = decoder.decode();
+        if matches!(instruction.mnemonic(), Mnemonic::Endbr32 | Mnemonic::Endbr64) {
+            instruction = decoder.decode();
+        }
+        if instruction.mnemonic() != Mnemonic::Jmp || instruction.op0_kind() != OpKind::Memory {
+            return None;
+        }
+        branch_target(&instruction).filter(|slot| self.imports.contains_key(slot))
+    }
+
+    /// Returns an iterator over the addresses and the contents of the executable sections.
+    pub fn code_sections(&self) -> impl Iterator<Item = (u64, &'data [u8])> + '_ {
+        self.sections
+            .iter()
+            .filter(|section| section.executable)
+            .map(|section| (section.address, section.data))
+    }
+
+    /// Returns the code starting at the given address up to the end of its section.
+    pub fn code_at(&self, addr: u64) -> Option<&'data [u8]> {
+        self.section_at(addr)
+            .filter(|section| section.executable)?
+            .data_from(addr)
+    }
+
+    /// Returns the contents of any section starting at the given address up to the end of
+    /// the section.
+    pub fn data_at(&self, addr: u64) -> Option<&'data [u8]> {
+        self.section_at(addr)?.data_from(addr)
+    }
+
+    /// Reads a pointer stored at the given address.
+    pub fn read_pointer(&self, addr: u64) -> Option<u64> {
+        let data = self.data_at(addr)?;
+        match self.bitness {
+            32 => Some(u32::from_le_bytes(data.get(..4)?.try_into().ok()?).into()),
+            _ => Some(u64::from_le_bytes(data.get(..8)?.try_into().ok()?)),
+        }
+    }
+
+    /// Decodes a NUL-terminated ASCII or UTF-16 string stored in read-only data at the given
+    /// address. Strings shorter than a few characters or containing control characters other
+    /// than whitespace are rejected.
+    pub fn string_at(&self, addr: u64) -> Option<String> {
+        let data = self
+            .section_at(addr)
+            .filter(|section| section.read_only)?
+            .data_from(addr)?;
+        let data = &data[..data.len().min(MAX_STRING_LEN)];
+        decode_ascii(data).or_else(|| decode_utf16(data))
+    }
+
+    /// Checks whether the address lies in any section of the object, including the parts of
+    /// sections that aren't backed by file data.
+    pub fn is_mapped(&self, addr: u64) -> bool {
+        self.ranges.iter().any(|range| range.contains(&addr))
+    }
+
+    /// Checks whether there is code at the given address.
+    pub fn is_code(&self, addr: u64) -> bool {
+        self.code_at(addr).is_some_and(|code| !code.is_empty())
     }
 
-    /// Returns the base address of the text section.
-    pub fn text_section_base(&self) -> u64 {
-        self.text.address()
+    fn section_at(&self, addr: u64) -> Option<&MappedSection<'data>> {
+        let idx = self.sections.partition_point(|section| section.address <= addr);
+        self.sections.get(idx.checked_sub(1)?)
     }
 }
 
+/// A section of an object file with its contents.
+#[derive(Debug)]
+struct MappedSection<'data> {
+    address: u64,
+    data: &'data [u8],
+    executable: bool,
+    read_only: bool,
+}
+
+impl<'data> MappedSection<'data> {
+    fn new(address: u64, data: &'data [u8], executable: bool, read_only: bool) -> Self {
+        Self {
+            address,
+            data,
+            executable,
+            read_only,
+        }
+    }
+
+    fn data_from(&self, addr: u64) -> Option<&'data [u8]> {
+        self.data.get(usize::try_from(addr - self.address).ok()?..)
+    }
+}
+
+/// A function symbol found in an object file.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionSymbol<'data> {
+    /// The name of the symbol.
+    pub name: &'data str,
+    /// The address of the symbol.
+    pub address: u64,
+    /// The size of the function or zero if it's unknown.
+    pub size: u64,
+}
+
+/// Options controlling the metadata extracted for each function.
+#[derive(Debug, Default, Clone)]
+pub struct LoadOptions {
+    /// Build a control flow graph of basic blocks for each function.
+    pub cfg: bool,
+    /// Load the functions found by [`ObjectCode::function_starts`] as well, including the ones
+    /// that can't be reached from the entrypoint, the seeds or the symbols.
+    pub function_starts: bool,
+}
+
 /// Metadata for code extracted from an object file.
 #[derive(Debug, Default)]
 pub struct CodeMetadata {
@@ -58,90 +346,239 @@ pub struct CodeMetadata {
 
 impl CodeMetadata {
     /// Loads an object file using the provided path.
-    pub fn load(obj: &ObjectCode<'_, '_>, seeds: impl IntoIterator<Item = u64>) -> Result<Self, Error> {
-        let slice = obj.text.data().map_err(|err| Error::Other(err.into()))?;
+    pub fn load(obj: &ObjectCode<'_>, seeds: impl IntoIterator<Item = u64>) -> Result<Self, Error> {
+        Self::load_with_options(obj, seeds, &LoadOptions::default())
+    }
+
+    /// Loads an object file extracting the metadata selected by the options.
+    pub fn load_with_options(
+        obj: &ObjectCode<'_>,
+        seeds: impl IntoIterator<Item = u64>,
+        options: &LoadOptions,
+    ) -> Result<Self, Error> {
         let mut object = Self::default();
-        object.load_func(obj.entrypoint(), slice);
+        object.load_func(obj.entrypoint(), obj, options);
         for seed in seeds {
-            object.load_func(seed, slice);
+            object.load_func(seed, obj, options);
+        }
+        for sym in obj.symbols() {
+            object.load_func(sym.address, obj, options);
+        }
+        if options.function_starts {
+            for addr in obj.function_starts() {
+                object.load_func(addr, obj, options);
+            }
         }
+        object.compute_depths(obj.entrypoint());
         Ok(object)
     }
 
-    fn load_func(&mut self, addr: u64, segment: &[u8]) {
+    /// Records the depth of each function in the call graph starting from the entrypoint.
+    pub(crate) fn compute_depths(&mut self, entry: u64) {
+        for (addr, depth) in self.call_graph.depths(entry) {
+            if let Some(func) = self.functions.get_mut(&addr) {
+                func.depth = Some(depth);
+            }
+        }
+    }
+
+    fn load_func(&mut self, addr: u64, obj: &ObjectCode<'_>, options: &LoadOptions) {
         let mut instruction = Instruction::default();
         let mut work = vec![addr];
 
         while let Some(addr) = work.pop() {
-            if self.call_graph.has_vertex(addr) {
+            if self.functions.contains_key(&addr) {
+                continue;
+            }
+            if let Some(name) = obj.import(addr) {
+                self.functions.insert(addr, FunctionMetadata::imported(name));
                 continue;
             }
+            // addresses outside of the executable sections are treated as external functions
+            let Some(code) = obj.code_at(addr).filter(|code| !code.is_empty()) else {
+                self.functions.insert(addr, FunctionMetadata::default());
+                continue;
+            };
 
-            let addr_usize = addr as usize;
-            let len = segment[addr_usize..]
-                .windows(16)
-                .position(is_endp)
-                .unwrap_or(segment.len() - addr_usize);
+            let len = match obj.function_end(addr) {
+                Some(end) => usize::try_from(end - addr).map_or(code.len(), |len| len.min(code.len())),
+                None => chunk_len(code),
+            };
 
-            let body = &segment[addr_usize..addr_usize + len];
-            self.functions.insert(addr, FunctionMetadata::from_slice(body));
+            // The function body starts as a single chunk of code and it's extended with chunks
+            // reached through jump tables that lie outside of it.
+            let entry_chunk = addr..addr + len as u64;
+            let mut chunks = vec![entry_chunk];
+            let exact_bounds = obj.function_end(addr).is_some();
+            let mut opcodes = vec![];
+            let mut tokens = vec![];
+            let mut targets = vec![];
+            let mut tracker = JumpTableTracker::default();
+            let mut cfg = options.cfg.then(CfgBuilder::default);
+            let mut hasher = DefaultHasher::new();
+            let mut strings = vec![];
+            let mut constants = vec![];
+            let mut i = 0;
 
-            let mut decoder = Decoder::new(64, body, 0);
+            while let Some(chunk) = chunks.get(i).cloned() {
+                let code = obj.code_at(chunk.start).unwrap_or_default();
+                let body = &code[..(chunk.end - chunk.start) as usize];
+                // The decoder is positioned at the virtual address of the chunk, so that branch
+                // targets and absolute memory operands can be resolved the same way in both modes.
+                let mut decoder = Decoder::with_ip(obj.bitness(), body, chunk.start, DecoderOptions::NONE);
 
-            while decoder.can_decode() {
-                decoder.decode_out(&mut instruction);
+                while decoder.can_decode() {
+                    decoder.decode_out(&mut instruction);
+                    opcodes.push(instruction.mnemonic());
+                    tokens.push(Token::new(&instruction));
+                    normalize_instruction(&instruction).hash(&mut hasher);
+                    if instruction.mnemonic() == Mnemonic::Lea && instruction.is_ip_rel_memory_operand() {
+                        let string = obj.string_at(instruction.ip_rel_memory_address());
+                        strings.extend(string.map(String::into_boxed_str));
+                    }
+                    constants.extend(distinctive_constants(&instruction, obj));
+                    if let Some(cfg) = &mut cfg {
+                        cfg.push(&instruction);
+                    }
 
-                match instruction.mnemonic() {
-                    Mnemonic::Call | Mnemonic::Jmp => {
-                        let rel_addr = instruction.memory_displacement64();
-                        let next_addr = if instruction.memory_size() == MemorySize::QwordOffset {
-                            addr + rel_addr
-                        } else {
-                            let rel_addr = rel_addr as i64;
-                            if rel_addr.is_negative() && rel_addr.unsigned_abs() > addr {
-                                continue;
+                    match (instruction.mnemonic(), branch_target(&instruction)) {
+                        (Mnemonic::Call | Mnemonic::Jmp, Some(target)) if instruction.op0_kind() == OpKind::Memory => {
+                            targets.push((target, EdgeKind::Indirect));
+                        }
+                        (Mnemonic::Call, Some(target)) => targets.push((target, EdgeKind::Call)),
+                        (Mnemonic::Jmp, Some(target)) => targets.push((target, EdgeKind::TailJump)),
+                        (Mnemonic::Jmp, None) => {
+                            let cases = tracker.targets(&instruction, obj);
+                            for &case in &cases {
+                                add_chunk(&mut chunks, case, obj);
                             }
-                            addr.checked_add_signed(rel_addr).unwrap()
-                        };
-
-                        if !(addr..addr + len as u64).contains(&next_addr) {
-                            self.call_graph.add_edge(addr, next_addr);
-                            if (0..segment.len() as u64).contains(&next_addr) {
-                                work.push(next_addr);
+                            if let Some(cfg) = &mut cfg {
+                                cfg.add_cases(instruction.ip(), &cases);
+                            }
+                        }
+                        // A conditional branch leaving a function with guessed boundaries means
+                        // that the guess was too short, otherwise it's a conditional tail call.
+                        (_, Some(target)) if instruction.is_jcc_short_or_near() => {
+                            if exact_bounds {
+                                targets.push((target, EdgeKind::TailJump));
                             } else {
-                                self.functions.insert(next_addr, FunctionMetadata::default());
+                                add_chunk(&mut chunks, target, obj);
                             }
                         }
+                        _ => {}
                     }
-                    _ => {}
+                    tracker.update(&instruction);
+                }
+                i += 1;
+            }
+            let mut func = FunctionMetadata::new(opcodes);
+            func.tokens = tokens;
+            func.cfg = cfg.map(|cfg| cfg.build(addr));
+            func.hash = hasher.finish();
+            func.strings = strings;
+            constants.sort_unstable();
+            constants.dedup();
+            func.constants = constants;
+
+            for (target, kind) in targets {
+                if chunks.iter().any(|chunk| chunk.contains(&target)) {
+                    continue;
                 }
+                let (target, kind) = match kind {
+                    EdgeKind::Indirect if obj.import(target).is_some() => (target, EdgeKind::Import),
+                    // the slot is replaced with the function it points at when it's known
+                    EdgeKind::Indirect => match obj.read_pointer(target).filter(|&ptr| obj.is_code(ptr)) {
+                        Some(ptr) => (ptr, EdgeKind::Indirect),
+                        // the slot is filled at runtime, its address isn't a function
+                        None => continue,
+                    },
+                    // calls to thunks of imported functions are attributed to the imports
+                    _ => match obj.import_thunk(target) {
+                        Some(slot) => (slot, EdgeKind::Import),
+                        None => (target, kind),
+                    },
+                };
+                self.call_graph.add_edge(addr, target, kind);
+                work.push(target);
             }
+            func.chunks = chunks;
+            self.functions.insert(addr, func);
         }
     }
 
-    /// Returns the call graph with relative addresses as vertices.
+    /// Returns the call graph with virtual addresses as vertices.
     #[inline]
     pub fn call_graph(&self) -> &Graph<u64> {
         &self.call_graph
     }
 
-    /// Returns the function metadata for the given relative address.
+    /// Returns an iterator over the addresses of all loaded functions, including the imported
+    /// and external ones.
+    pub fn functions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.functions.keys().copied()
+    }
+
+    /// Returns the control flow graph of the function at the given address. The graphs are only
+    /// available when they're enabled in [`LoadOptions`].
+    pub fn cfg(&self, addr: u64) -> Option<&ControlFlowGraph> {
+        self.functions.get(&addr)?.cfg()
+    }
+
+    /// Decodes the instructions of the function at the given address sorted by their addresses.
+    /// Instructions of overlapping chunks are only returned once.
+    pub fn instructions(&self, obj: &ObjectCode<'_>, addr: u64) -> Option<Vec<Instruction>> {
+        let mut instructions = vec![];
+        for chunk in &self.functions.get(&addr)?.chunks {
+            let code = obj.code_at(chunk.start)?;
+            let body = code.get(..(chunk.end - chunk.start) as usize)?;
+            instructions.extend(Decoder::with_ip(obj.bitness(), body, chunk.start, DecoderOptions::NONE));
+        }
+        instructions.sort_by_key(Instruction::ip);
+        instructions.dedup_by_key(|instruction| instruction.ip());
+        Some(instructions)
+    }
+
+    /// Returns the function metadata for the given address.
     #[inline]
     pub(crate) fn get_function(&self, addr: u64) -> Option<&FunctionMetadata> {
         self.functions.get(&addr)
     }
 }
 
+#[cfg(test)]
+impl CodeMetadata {
+    /// Creates metadata of functions with the given opcodes connected with calls. The addresses
+    /// of the calls and the functions are offsets from the base address. The instructions have
+    /// no operands.
+    pub(crate) fn with_calls(base: u64, calls: &[(u64, u64)], functions: &[(u64, &[Mnemonic])]) -> Self {
+        let mut call_graph = Graph::new();
+        for &(caller, callee) in calls {
+            call_graph.add_edge(base + caller, base + callee, EdgeKind::Call);
+        }
+        let functions = functions
+            .iter()
+            .map(|&(offset, opcodes)| {
+                let mut func = FunctionMetadata::new(opcodes.to_vec());
+                func.tokens = opcodes.iter().copied().map(Token::from_mnemonic).collect();
+                (base + offset, func)
+            })
+            .collect();
+        Self { call_graph, functions }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
-    MissingTextSection,
+    MissingCodeSection,
+    UnsupportedArchitecture(Architecture),
     Other(Box<dyn StdError>),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::MissingTextSection => write!(f, "missing .text section"),
+            Error::MissingCodeSection => write!(f, "no executable sections"),
+            Error::UnsupportedArchitecture(arch) => write!(f, "unsupported architecture: {:?}", arch),
             Error::Other(err) => write!(f, "{}", err),
         }
     }
@@ -152,12 +589,31 @@ impl StdError for Error {}
 #[derive(Debug, Default, Clone)]
 pub(crate) struct FunctionMetadata {
     opcodes: Vec<Mnemonic>,
+    tokens: Vec<Token>,
+    import: Option<Box<str>>,
+    cfg: Option<ControlFlowGraph>,
+    chunks: Vec<Range<u64>>,
+    hash: u64,
+    strings: Vec<Box<str>>,
+    constants: Vec<u64>,
+    depth: Option<usize>,
 }
 
 impl FunctionMetadata {
     #[inline]
     pub fn new(opcodes: Vec<Mnemonic>) -> Self {
-        Self { opcodes }
+        Self {
+            opcodes,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn imported(name: impl Into<Box<str>>) -> Self {
+        Self {
+            import: Some(name.into()),
+            ..Default::default()
+        }
     }
 
     #[inline]
@@ -165,18 +621,320 @@ impl FunctionMetadata {
         &self.opcodes
     }
 
-    pub fn from_slice(slice: &[u8]) -> Self {
-        let mut opcodes = vec![];
-        let mut decoder = Decoder::new(64, slice, 0);
-        let mut instruction = Instruction::default();
+    /// Returns the instructions normalized to their mnemonics and kinds of operands.
+    #[inline]
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    #[inline]
+    pub fn import_name(&self) -> Option<&str> {
+        self.import.as_deref()
+    }
+
+    #[inline]
+    pub fn cfg(&self) -> Option<&ControlFlowGraph> {
+        self.cfg.as_ref()
+    }
+
+    /// Returns a hash of the instructions with their operands, computed without the branch
+    /// targets and relative addresses.
+    #[inline]
+    pub fn instruction_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Returns the strings referenced by the function.
+    #[inline]
+    pub fn strings(&self) -> &[Box<str>] {
+        &self.strings
+    }
+
+    /// Replaces the strings referenced by the function.
+    #[cfg(test)]
+    pub fn with_strings<S: Into<Box<str>>>(mut self, strings: impl IntoIterator<Item = S>) -> Self {
+        self.strings = strings.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Returns the distinctive immediates and displacements used by the function, sorted and
+    /// without duplicates.
+    #[inline]
+    pub fn constants(&self) -> &[u64] {
+        &self.constants
+    }
+
+    /// Returns the number of calls on the shortest path from the entrypoint to the function,
+    /// if it can be reached from the entrypoint.
+    #[inline]
+    pub fn depth(&self) -> Option<usize> {
+        self.depth
+    }
+}
+
+/// Reads function boundaries from the exception directory (.pdata) of a PE32+ file.
+/// The returned map is keyed by function start addresses.
+fn load_exception_directory(pe: &PeFile64<'_>) -> Result<HashMap<u64, u64>, Error> {
+    let Some(dir) = pe.data_directory(IMAGE_DIRECTORY_ENTRY_EXCEPTION) else {
+        return Ok(HashMap::new());
+    };
+    let sections = pe.section_table();
+    let data = dir.data(pe.data(), &sections).map_err(|err| Error::Other(err.into()))?;
+    let count = data.len() / mem::size_of::<ImageRuntimeFunctionEntry>();
+    let (entries, _) = object::pod::slice_from_bytes::<ImageRuntimeFunctionEntry>(data, count)
+        .map_err(|()| Error::Other("invalid exception directory".into()))?;
+
+    let unwind_flags = |entry: &ImageRuntimeFunctionEntry| {
+        sections
+            .pe_data_at(pe.data(), entry.unwind_info_address_or_data.get(LE))
+            .and_then(|info| info.first())
+            .map_or(0, |&b| b >> 3)
+    };
+    Ok(runtime_function_bounds(
+        entries,
+        pe.relative_address_base(),
+        unwind_flags,
+    ))
+}
+
+/// Collects the function boundaries described by runtime function entries. Entries with chained
+/// unwind info describe fragments of other functions and are skipped along with empty entries.
+fn runtime_function_bounds(
+    entries: &[ImageRuntimeFunctionEntry],
+    image_base: u64,
+    unwind_flags: impl Fn(&ImageRuntimeFunctionEntry) -> u8,
+) -> HashMap<u64, u64> {
+    let mut bounds = HashMap::new();
+    for entry in entries {
+        let begin = u64::from(entry.begin_address.get(LE));
+        let end = u64::from(entry.end_address.get(LE));
+        if unwind_flags(entry) & UNW_FLAG_CHAININFO == 0 && end > begin {
+            bounds.insert(image_base + begin, image_base + end);
+        }
+    }
+    bounds
+}
+
+/// Reads the import table of a PE file. The returned map is keyed by the addresses of IAT slots.
+fn load_import_table<Pe: ImageNtHeaders>(pe: &PeFile<'_, Pe>) -> Result<HashMap<u64, Box<str>>, Error> {
+    let mut imports = HashMap::new();
+    let Some(table) = pe.import_table().map_err(|err| Error::Other(err.into()))? else {
+        return Ok(imports);
+    };
+    let image_base = pe.relative_address_base();
+    let thunk_size = mem::size_of::<Pe::ImageThunkData>() as u64;
+
+    let mut descriptors = table.descriptors().map_err(|err| Error::Other(err.into()))?;
+    while let Some(desc) = descriptors.next().map_err(|err| Error::Other(err.into()))? {
+        let library = table.name(desc.name.get(LE)).map_err(|err| Error::Other(err.into()))?;
+        let library = String::from_utf8_lossy(library).to_ascii_lowercase();
+        let first_thunk = desc.first_thunk.get(LE);
+        let lookup_thunk = match desc.original_first_thunk.get(LE) {
+            0 => first_thunk,
+            addr => addr,
+        };
+        let mut thunks = table.thunks(lookup_thunk).map_err(|err| Error::Other(err.into()))?;
+
+        let mut slot = image_base + u64::from(first_thunk);
+        while let Some(thunk) = thunks.next::<Pe>().map_err(|err| Error::Other(err.into()))? {
+            let name = match table.import::<Pe>(thunk) {
+                Ok(Import::Name(_, name)) => String::from_utf8_lossy(name).into(),
+                Ok(Import::Ordinal(ordinal)) => format!("{}#{}", library, ordinal).into(),
+                Err(_) => format!("{}#{:X}", library, thunk.raw()).into(),
+            };
+            imports.insert(slot, name);
+            slot += thunk_size;
+        }
+    }
+    Ok(imports)
+}
+
+/// Reads the dynamic relocations of an ELF file that bind slots in the GOT to undefined symbols.
+/// The returned map is keyed by the addresses of the slots.
+fn load_dynamic_imports(file: &object::read::File<'_>) -> HashMap<u64, Box<str>> {
+    let (Some(relocations), Some(symbols)) = (file.dynamic_relocations(), file.dynamic_symbol_table()) else {
+        return HashMap::new();
+    };
+    relocations
+        .filter_map(|(slot, reloc)| {
+            let RelocationTarget::Symbol(idx) = reloc.target() else {
+                return None;
+            };
+            let sym = symbols.symbol_by_index(idx).ok()?;
+            let name = sym.name().ok().filter(|name| sym.is_undefined() && !name.is_empty())?;
+            Some((slot, name.into()))
+        })
+        .collect()
+}
+
+/// Collects the function symbols defined in the executable sections from the symbol table,
+/// the dynamic symbol table and the exports of an object file.
+fn load_function_symbols<'data>(
+    file: &object::read::File<'data>,
+    obj: &ObjectCode<'data>,
+) -> Vec<FunctionSymbol<'data>> {
+    let symbols = file
+        .symbols()
+        .chain(file.dynamic_symbols())
+        .filter(|sym| sym.kind() == SymbolKind::Text && sym.is_definition())
+        .filter_map(|sym| Some((sym.name().ok()?, sym.address(), sym.size())));
+    let exports = file.exports().unwrap_or_default().into_iter().filter_map(|export| {
+        let name = std::str::from_utf8(export.name()).ok()?;
+        Some((name, export.address(), 0))
+    });
+
+    merge_symbols(symbols.chain(exports), obj)
+}
 
-        while decoder.can_decode() {
-            decoder.decode_out(&mut instruction);
-            opcodes.push(instruction.mnemonic());
+/// Drops the symbols without names or outside of the executable sections and merges the symbols
+/// with the same name at the same address. Aliases with different names are all kept, because
+/// each of them can be the one shared with another file.
+fn merge_symbols<'data>(
+    symbols: impl IntoIterator<Item = (&'data str, u64, u64)>,
+    obj: &ObjectCode<'_>,
+) -> Vec<FunctionSymbol<'data>> {
+    let mut result: Vec<_> = symbols
+        .into_iter()
+        .filter(|(name, _, _)| !name.is_empty())
+        .filter_map(|(name, address, size)| {
+            let code = obj.code_at(address).filter(|code| !code.is_empty())?;
+            let size = size.min(code.len() as u64);
+            Some(FunctionSymbol { name, address, size })
+        })
+        .collect();
+    // prefer the symbols with known sizes when merging
+    result.sort_by_key(|sym| (sym.address, sym.name, sym.size == 0));
+    result.dedup_by_key(|sym| (sym.address, sym.name));
+    result
+}
+
+/// Returns the virtual address a call or a jump transfers control to. For indirect branches
+/// through memory, the address of the memory slot holding the target is returned instead.
+/// Branches through registers and through computed memory operands cannot be resolved.
+fn branch_target(instruction: &Instruction) -> Option<u64> {
+    match instruction.op0_kind() {
+        OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => Some(instruction.near_branch_target()),
+        // call [rip+disp], only encodable in 64-bit mode
+        OpKind::Memory if instruction.is_ip_rel_memory_operand() => Some(instruction.ip_rel_memory_address()),
+        // call [disp32], an absolute slot address, typical for 32-bit code
+        OpKind::Memory
+            if instruction.memory_base() == Register::None && instruction.memory_index() == Register::None =>
+        {
+            Some(instruction.memory_displacement64())
         }
+        _ => None,
+    }
+}
 
-        Self::new(opcodes)
+/// Clears the branch targets and the instruction pointer relative addresses of an instruction,
+/// because they change whenever code is moved.
+pub(crate) fn normalize_instruction(instruction: &Instruction) -> Instruction {
+    let mut instruction = *instruction;
+    let relative = (0..instruction.op_count()).any(|i| {
+        matches!(
+            instruction.op_kind(i),
+            OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+        )
+    });
+    if relative || instruction.is_ip_rel_memory_operand() {
+        instruction.set_memory_displacement64(0);
     }
+    instruction
+}
+
+/// Returns the immediates and memory displacements of an instruction that are distinctive
+/// enough to identify a function. Small values, stack offsets and values pointing into the
+/// object are skipped.
+fn distinctive_constants<'a>(instruction: &'a Instruction, obj: &'a ObjectCode<'_>) -> impl Iterator<Item = u64> + 'a {
+    let immediates = (0..instruction.op_count()).filter_map(move |i| match instruction.op_kind(i) {
+        OpKind::Immediate8
+        | OpKind::Immediate16
+        | OpKind::Immediate32
+        | OpKind::Immediate64
+        | OpKind::Immediate8to16
+        | OpKind::Immediate8to32
+        | OpKind::Immediate8to64
+        | OpKind::Immediate32to64 => Some(instruction.immediate(i)),
+        _ => None,
+    });
+    let has_memory = (0..instruction.op_count()).any(|i| instruction.op_kind(i) == OpKind::Memory);
+    let displacement =
+        (has_memory && !instruction.is_ip_rel_memory_operand()).then(|| instruction.memory_displacement64());
+
+    immediates.chain(displacement).filter(move |&value| {
+        // 32-bit values are compared as such, so that negative values of any size are skipped
+        let magnitude = match u32::try_from(value) {
+            Ok(value) => u64::from((value as i32).unsigned_abs()),
+            Err(_) => (value as i64).unsigned_abs(),
+        };
+        magnitude >= MIN_CONSTANT && !obj.is_mapped(value)
+    })
+}
+
+fn is_data(section: &object::Section<'_, '_>) -> bool {
+    matches!(
+        section.kind(),
+        SectionKind::Data | SectionKind::ReadOnlyData | SectionKind::ReadOnlyDataWithRel | SectionKind::ReadOnlyString
+    )
+}
+
+fn is_read_only(section: &object::Section<'_, '_>) -> bool {
+    matches!(section.kind(), SectionKind::ReadOnlyData | SectionKind::ReadOnlyString)
+}
+
+fn is_executable(section: &object::Section<'_, '_>) -> bool {
+    match section.flags() {
+        SectionFlags::Elf { sh_flags } => sh_flags & u64::from(elf::SHF_EXECINSTR) != 0,
+        SectionFlags::Coff { characteristics } => {
+            characteristics & (pe::IMAGE_SCN_CNT_CODE | pe::IMAGE_SCN_MEM_EXECUTE) != 0
+        }
+        SectionFlags::MachO { flags } => {
+            flags & (macho::S_ATTR_PURE_INSTRUCTIONS | macho::S_ATTR_SOME_INSTRUCTIONS) != 0
+        }
+        _ => section.kind() == SectionKind::Text,
+    }
+}
+
+fn decode_ascii(data: &[u8]) -> Option<String> {
+    let bytes = &data[..data.iter().position(|&b| b == 0)?];
+    let is_valid = bytes.len() >= MIN_STRING_LEN
+        && bytes
+            .iter()
+            .all(|&b| b.is_ascii_graphic() || matches!(b, b' ' | b'\t' | b'\n' | b'\r'));
+    is_valid.then(|| String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn decode_utf16(data: &[u8]) -> Option<String> {
+    let units: Vec<_> = data
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    // the string must be terminated within the scanned data
+    if units.len() * 2 + 2 > data.len() {
+        return None;
+    }
+    let string = char::decode_utf16(units).collect::<Result<String, _>>().ok()?;
+    let is_valid = string.chars().count() >= MIN_STRING_LEN
+        && string
+            .chars()
+            .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'));
+    is_valid.then_some(string)
+}
+
+/// Adds a chunk of code starting at the given address to a function body unless it's
+/// already covered by one of the chunks.
+fn add_chunk(chunks: &mut Vec<Range<u64>>, addr: u64, obj: &ObjectCode<'_>) {
+    if !chunks.iter().any(|chunk| chunk.contains(&addr)) {
+        let len = obj.code_at(addr).map_or(0, chunk_len);
+        chunks.push(addr..addr + len as u64);
+    }
+}
+
+/// Returns the length of the chunk of code at the start of the slice, guessed by looking for
+/// the alignment padding that follows it.
+fn chunk_len(code: &[u8]) -> usize {
+    code.windows(16).position(is_endp).unwrap_or(code.len())
 }
 
 fn is_endp(slice: &[u8]) -> bool {
@@ -188,3 +946,244 @@ fn is_endp(slice: &[u8]) -> bool {
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use object::U32;
+    use test_case::test_case;
+
+    use super::*;
+
+    pub(super) fn test_object(sections: Vec<MappedSection<'_>>) -> ObjectCode<'_> {
+        let ranges = sections
+            .iter()
+            .map(|section| section.address..section.address + section.data.len() as u64)
+            .collect();
+        ObjectCode {
+            sections,
+            ranges,
+            entry: 0x1000,
+            bitness: 64,
+            function_bounds: HashMap::new(),
+            symbols: vec![],
+            imports: HashMap::new(),
+        }
+    }
+
+    // call rel32
+    #[test_case(32, &[0xE8, 0x0B, 0x00, 0x00, 0x00], Some(0x0040_1010))]
+    #[test_case(64, &[0xE8, 0x0B, 0x00, 0x00, 0x00], Some(0x0040_1010))]
+    // call rel32 with a negative displacement
+    #[test_case(32, &[0xE8, 0xFB, 0xFF, 0xFF, 0xFF], Some(0x0040_1000))]
+    // jmp rel8
+    #[test_case(32, &[0xEB, 0x0E], Some(0x0040_1010))]
+    // call dword ptr [0x402000]
+    #[test_case(32, &[0xFF, 0x15, 0x00, 0x20, 0x40, 0x00], Some(0x0040_2000))]
+    // call qword ptr [rip+0x1000]
+    #[test_case(64, &[0xFF, 0x15, 0x00, 0x10, 0x00, 0x00], Some(0x0040_2006))]
+    // call qword ptr [rax]
+    #[test_case(64, &[0xFF, 0x10], None)]
+    // call eax
+    #[test_case(32, &[0xFF, 0xD0], None)]
+    fn test_branch_target(bitness: u32, bytes: &[u8], expected: Option<u64>) {
+        let mut decoder = Decoder::with_ip(bitness, bytes, 0x0040_1000, DecoderOptions::NONE);
+        let instruction = decoder.decode();
+        assert_eq!(branch_target(&instruction), expected);
+    }
+
+    #[test_case(0x1000, Some(&[0x90, 0xC3]))]
+    #[test_case(0x1001, Some(&[0xC3]))]
+    #[test_case(0x1002, Some(&[]))]
+    #[test_case(0x2001, Some(&[0xCC]))]
+    #[test_case(0x0FFF, None)]
+    fn test_code_at(addr: u64, expected: Option<&[u8]>) {
+        let obj = test_object(vec![
+            MappedSection::new(0x1000, &[0x90, 0xC3], true, false),
+            MappedSection::new(0x2000, &[0xCC, 0xCC], true, false),
+        ]);
+        assert_eq!(obj.code_at(addr), expected);
+    }
+
+    #[test_case(b"usage: %s\n\0", Some("usage: %s\n"))]
+    #[test_case(b"e\0r\0r\0o\0r\0\0\0", Some("error"))]
+    #[test_case(b"abc\0", None)]
+    #[test_case(b"\x01\x02\x03\x04\0", None)]
+    #[test_case(b"unterminated", None)]
+    fn test_string_at(data: &[u8], expected: Option<&str>) {
+        let obj = test_object(vec![
+            MappedSection::new(0x1000, &[0xC3], true, false),
+            MappedSection::new(0x2000, data, false, true),
+        ]);
+        assert_eq!(obj.string_at(0x2000).as_deref(), expected);
+    }
+
+    #[test_case(&[0x35, 0x20, 0x83, 0xB8, 0xED], &[0xEDB8_8320]; "xor eax, 0xEDB88320")]
+    #[test_case(&[0x69, 0xC0, 0x93, 0x01, 0x00, 0x01], &[0x0100_0193]; "imul eax, eax, 0x1000193")]
+    #[test_case(&[0xB8, 0xFF, 0xFF, 0xFF, 0xFF], &[]; "mov eax, -1")]
+    #[test_case(&[0x48, 0x8B, 0x83, 0xA8, 0x01, 0x00, 0x00], &[]; "mov rax, [rbx + 0x1A8]")]
+    #[test_case(&[0x48, 0x8B, 0x83, 0x00, 0x20, 0x01, 0x00], &[0x0001_2000]; "mov rax, [rbx + 0x12000]")]
+    #[test_case(&[0x48, 0xC7, 0xC0, 0x00, 0x20, 0x00, 0x00], &[]; "mov rax, 0x2000")]
+    #[test_case(&[0x48, 0x8D, 0x05, 0x00, 0x00, 0x01, 0x00], &[]; "lea rax, [rip + 0x10000]")]
+    #[test_case(&[0x8B, 0x04, 0x25, 0x10, 0x30, 0x00, 0x00], &[]; "mov eax, [0x3010]")]
+    #[test_case(&[0xB8, 0x00, 0x31, 0x00, 0x00], &[0x3100]; "mov eax, 0x3100")]
+    fn test_distinctive_constants(code: &[u8], expected: &[u64]) {
+        let mut obj = test_object(vec![
+            MappedSection::new(0x1000, &[0xC3], true, false),
+            MappedSection::new(0x2000, &[0; 0x10], false, true),
+        ]);
+        // a .bss section without file data
+        obj.ranges.push(0x3000..0x3100);
+        let instruction = Decoder::with_ip(64, code, 0x1000, DecoderOptions::NONE).decode();
+        assert_eq!(distinctive_constants(&instruction, &obj).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_instruction_hash() {
+        let mut code = vec![0xCC; 0x200];
+        // call 0x1100, ret
+        code[..0x06].copy_from_slice(&[0xE8, 0xFB, 0x00, 0x00, 0x00, 0xC3]);
+        // call 0x1110, ret
+        code[0x10..0x16].copy_from_slice(&[0xE8, 0xFB, 0x00, 0x00, 0x00, 0xC3]);
+        // mov eax, 1, ret
+        code[0x20..0x26].copy_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+        // mov eax, 2, ret
+        code[0x30..0x36].copy_from_slice(&[0xB8, 0x02, 0x00, 0x00, 0x00, 0xC3]);
+        code[0x100] = 0xC3;
+        code[0x110] = 0xC3;
+
+        let obj = test_object(vec![MappedSection::new(0x1000, &code, true, false)]);
+        let metadata = CodeMetadata::load(&obj, [0x1010, 0x1020, 0x1030]).unwrap();
+        let hash = |addr| metadata.get_function(addr).unwrap().instruction_hash();
+        assert_eq!(hash(0x1000), hash(0x1010));
+        assert_ne!(hash(0x1020), hash(0x1030));
+    }
+
+    #[test]
+    fn test_runtime_function_bounds() {
+        let entry = |begin, end, unwind_info| ImageRuntimeFunctionEntry {
+            begin_address: U32::new(LE, begin),
+            end_address: U32::new(LE, end),
+            unwind_info_address_or_data: U32::new(LE, unwind_info),
+        };
+        let entries = [
+            entry(0x1000, 0x1040, 0),
+            entry(0x1040, 0x1080, UNW_FLAG_CHAININFO.into()),
+            entry(0x1080, 0x1080, 0),
+            entry(0x10A0, 0x1090, 0),
+            entry(0x10C0, 0x10D0, 0),
+        ];
+        let bounds = runtime_function_bounds(&entries, 0x1_4000_0000, |entry| {
+            entry.unwind_info_address_or_data.get(LE) as u8
+        });
+        assert_eq!(
+            bounds,
+            HashMap::from_iter([(0x1_4000_1000, 0x1_4000_1040), (0x1_4000_10C0, 0x1_4000_10D0)])
+        );
+    }
+
+    #[test]
+    fn test_function_bounds() {
+        let mut code = vec![0xCC; 0x100];
+        // xor eax, eax; ret; int3; int3; xor eax, eax; ret
+        let func = [0x31, 0xC0, 0xC3, 0xCC, 0xCC, 0x31, 0xC0, 0xC3];
+        code[..0x08].copy_from_slice(&func);
+        code[0x10..0x18].copy_from_slice(&func);
+
+        let mut obj = test_object(vec![MappedSection::new(0x1000, &code, true, false)]);
+        obj.function_bounds.insert(0x1000, 0x1008);
+        let metadata = CodeMetadata::load(&obj, [0x1010]).unwrap();
+        let opcodes = |addr| metadata.get_function(addr).unwrap().opcodes().to_vec();
+        assert_eq!(
+            opcodes(0x1000),
+            &[
+                Mnemonic::Xor,
+                Mnemonic::Ret,
+                Mnemonic::Int3,
+                Mnemonic::Int3,
+                Mnemonic::Xor,
+                Mnemonic::Ret
+            ]
+        );
+        // the function without an entry ends at the guessed boundary
+        assert_eq!(opcodes(0x1010), &[Mnemonic::Xor]);
+    }
+
+    #[test]
+    fn test_merge_symbols() {
+        let code = [0xC3; 0x10];
+        let obj = test_object(vec![MappedSection::new(0x1000, &code, true, false)]);
+        let symbols = merge_symbols(
+            [
+                ("main", 0x1000, 0),
+                ("main", 0x1000, 8),
+                ("_main", 0x1000, 0),
+                ("", 0x1008, 0),
+                ("data", 0x2000, 4),
+            ],
+            &obj,
+        );
+        let symbols: Vec<_> = symbols.iter().map(|sym| (sym.name, sym.address, sym.size)).collect();
+        assert_eq!(symbols, &[("_main", 0x1000, 0), ("main", 0x1000, 8)]);
+    }
+
+    #[test]
+    fn test_indirect_calls() {
+        let mut code = vec![0xCC; 0x200];
+        code[..0x13].copy_from_slice(&[
+            0xFF, 0x15, 0xFA, 0x1F, 0x00, 0x00, // call qword ptr [rip+0x1FFA]
+            0xFF, 0x15, 0xFC, 0x1F, 0x00, 0x00, // call qword ptr [rip+0x1FFC]
+            0xFF, 0x15, 0xFE, 0x1F, 0x00, 0x00, // call qword ptr [rip+0x1FFE]
+            0xC3, // ret
+        ]);
+        code[0x100] = 0xC3;
+        let mut slots = [0; 0x18];
+        slots[..8].copy_from_slice(&0x1100u64.to_le_bytes());
+
+        let mut obj = test_object(vec![
+            MappedSection::new(0x1000, &code, true, false),
+            MappedSection::new(0x3000, &slots, false, false),
+        ]);
+        obj.imports.insert(0x3010, "ExitProcess".into());
+        let metadata = CodeMetadata::load(&obj, []).unwrap();
+        let star = metadata.call_graph().get_star(0x1000);
+        let mut edges: Vec<_> = star.edges().copied().collect();
+        edges.sort_unstable();
+        assert_eq!(edges, &[0x1100, 0x3010]);
+        assert!(metadata.get_function(0x3008).is_none());
+        assert_eq!(
+            metadata.get_function(0x3010).unwrap().import_name(),
+            Some("ExitProcess")
+        );
+    }
+
+    #[test]
+    fn test_jump_table_cases() {
+        let mut code = vec![0xCC; 0x300];
+        code[..0x18].copy_from_slice(&[
+            0x83, 0xFF, 0x01, // cmp edi, 1
+            0x77, 0x10, // ja 0x1015
+            0x48, 0x8D, 0x15, 0xF4, 0x1F, 0x00, 0x00, // lea rdx, [rip+0x1FF4]
+            0x48, 0x63, 0x04, 0xBA, // movsxd rax, dword ptr [rdx+rdi*4]
+            0x48, 0x01, 0xD0, // add rax, rdx
+            0xFF, 0xE0, // jmp rax
+            0xC3, 0xCC, 0xCC, // ret
+        ]);
+        // call 0x1200, ret
+        code[0x100..0x106].copy_from_slice(&[0xE8, 0xFB, 0x00, 0x00, 0x00, 0xC3]);
+        // call 0x1210, ret
+        code[0x110..0x116].copy_from_slice(&[0xE8, 0xFB, 0x00, 0x00, 0x00, 0xC3]);
+        // ret
+        code[0x200] = 0xC3;
+        code[0x210] = 0xC3;
+        let table = [0x00, 0xE1, 0xFF, 0xFF, 0x10, 0xE1, 0xFF, 0xFF];
+
+        let obj = test_object(vec![
+            MappedSection::new(0x1000, &code, true, false),
+            MappedSection::new(0x3000, &table, false, true),
+        ]);
+        let metadata = CodeMetadata::load(&obj, []).unwrap();
+        let star = metadata.call_graph().get_star(0x1000);
+        assert_eq!(star.edges().copied().collect::<Vec<_>>(), &[0x1200, 0x1210]);
+        assert!(metadata.get_function(0x1100).is_none());
+    }
+}
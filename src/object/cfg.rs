@@ -0,0 +1,395 @@
+use hashbrown::{HashMap, HashSet};
+use iced_x86::{FlowControl, Instruction, Mnemonic, OpKind};
+
+/// The control flow graph of a function, made of basic blocks sorted by address.
+#[derive(Debug, Default, Clone)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    /// Returns the basic blocks sorted by address.
+    #[inline]
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Returns the basic block starting at the given address.
+    pub fn block_at(&self, addr: u64) -> Option<&BasicBlock> {
+        let idx = self.blocks.binary_search_by_key(&addr, |block| block.address).ok()?;
+        self.blocks.get(idx)
+    }
+
+    /// Returns the number of edges between the basic blocks.
+    pub fn edge_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.successors.len()).sum()
+    }
+
+    /// Returns the number of loops, counted by their headers.
+    pub fn loop_count(&self) -> usize {
+        self.blocks.iter().filter(|block| block.loop_header).count()
+    }
+
+    /// Returns the maximum nesting depth of loops.
+    pub fn max_loop_depth(&self) -> usize {
+        self.blocks.iter().map(|block| block.loop_depth).max().unwrap_or(0)
+    }
+
+    /// Returns a summary of the structure of the graph.
+    pub fn shape(&self) -> Shape {
+        Shape {
+            blocks: self.blocks.len(),
+            edges: self.edge_count(),
+            loops: self.loop_count(),
+            loop_depth: self.max_loop_depth(),
+        }
+    }
+
+    /// Computes the structural distance between two graphs. The distance doesn't depend on the
+    /// order of the blocks in the code, so it's not affected by the compiler rearranging them.
+    pub fn distance(&self, other: &Self) -> usize {
+        let (lhs, rhs) = (self.shape(), other.shape());
+        lhs.blocks.abs_diff(rhs.blocks)
+            + lhs.edges.abs_diff(rhs.edges)
+            + lhs.loops.abs_diff(rhs.loops)
+            + lhs.loop_depth.abs_diff(rhs.loop_depth)
+    }
+
+    /// Computes the structural distance divided by the largest distance possible for graphs of
+    /// these sizes, so that it's between 0 and 1.
+    pub fn normalized_distance(&self, other: &Self) -> f64 {
+        let (lhs, rhs) = (self.shape(), other.shape());
+        let max = lhs.blocks.max(rhs.blocks)
+            + lhs.edges.max(rhs.edges)
+            + lhs.loops.max(rhs.loops)
+            + lhs.loop_depth.max(rhs.loop_depth);
+        match max {
+            0 => 0.,
+            _ => self.distance(other) as f64 / max as f64,
+        }
+    }
+}
+
+/// A sequence of instructions that's only entered at the start and only left at the end.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    address: u64,
+    end: u64,
+    opcodes: Vec<Mnemonic>,
+    successors: Vec<usize>,
+    loop_header: bool,
+    loop_depth: usize,
+}
+
+impl BasicBlock {
+    fn new(address: u64) -> Self {
+        Self {
+            address,
+            end: address,
+            opcodes: vec![],
+            successors: vec![],
+            loop_header: false,
+            loop_depth: 0,
+        }
+    }
+
+    /// Returns the address of the first instruction.
+    #[inline]
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Returns the address following the last instruction.
+    #[inline]
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Returns the opcodes of the instructions.
+    #[inline]
+    pub fn opcodes(&self) -> &[Mnemonic] {
+        &self.opcodes
+    }
+
+    /// Returns the indices of the blocks that control can be transferred to.
+    #[inline]
+    pub fn successors(&self) -> &[usize] {
+        &self.successors
+    }
+
+    /// Checks whether the block is the target of a back edge.
+    #[inline]
+    pub fn is_loop_header(&self) -> bool {
+        self.loop_header
+    }
+
+    /// Returns the number of loops the block is nested in.
+    #[inline]
+    pub fn loop_depth(&self) -> usize {
+        self.loop_depth
+    }
+}
+
+/// A summary of the structure of a control flow graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shape {
+    /// The number of basic blocks.
+    pub blocks: usize,
+    /// The number of edges between basic blocks.
+    pub edges: usize,
+    /// The number of loops.
+    pub loops: usize,
+    /// The maximum nesting depth of loops.
+    pub loop_depth: usize,
+}
+
+/// Collects the instructions of a function as they're decoded and builds a control flow graph
+/// out of them.
+#[derive(Debug, Default)]
+pub(crate) struct CfgBuilder {
+    instructions: Vec<DecodedInstruction>,
+    cases: HashMap<u64, Vec<u64>>,
+}
+
+impl CfgBuilder {
+    /// Records a decoded instruction.
+    pub fn push(&mut self, instruction: &Instruction) {
+        let target = match instruction.op0_kind() {
+            OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+                Some(instruction.near_branch_target())
+            }
+            _ => None,
+        };
+        self.instructions.push(DecodedInstruction {
+            address: instruction.ip(),
+            next: instruction.next_ip(),
+            mnemonic: instruction.mnemonic(),
+            flow: instruction.flow_control(),
+            target,
+        });
+    }
+
+    /// Records the targets of an indirect jump at the given address.
+    pub fn add_cases(&mut self, addr: u64, cases: &[u64]) {
+        self.cases.entry(addr).or_default().extend_from_slice(cases);
+    }
+
+    /// Splits the recorded instructions into basic blocks, connects them and finds the loops
+    /// in the graph.
+    pub fn build(mut self, entry: u64) -> ControlFlowGraph {
+        // chunks of a function can overlap, so the same instruction can be decoded twice
+        self.instructions.sort_by_key(|insn| insn.address);
+        self.instructions.dedup_by_key(|insn| insn.address);
+
+        let mut leaders = HashSet::new();
+        leaders.insert(entry);
+        leaders.extend(self.cases.values().flatten().copied());
+        let mut prev_next = None;
+        for insn in &self.instructions {
+            if prev_next != Some(insn.address) {
+                leaders.insert(insn.address);
+            }
+            match insn.flow {
+                FlowControl::ConditionalBranch | FlowControl::UnconditionalBranch => {
+                    leaders.extend(insn.target);
+                    leaders.insert(insn.next);
+                }
+                FlowControl::IndirectBranch | FlowControl::Return | FlowControl::Exception => {
+                    leaders.insert(insn.next);
+                }
+                _ => {}
+            }
+            prev_next = Some(insn.next);
+        }
+
+        let mut blocks: Vec<BasicBlock> = vec![];
+        let mut exits: Vec<&DecodedInstruction> = vec![];
+        for insn in &self.instructions {
+            if blocks.is_empty() || leaders.contains(&insn.address) {
+                blocks.push(BasicBlock::new(insn.address));
+                exits.push(insn);
+            }
+            let block = blocks.last_mut().unwrap();
+            block.end = insn.next;
+            block.opcodes.push(insn.mnemonic);
+            *exits.last_mut().unwrap() = insn;
+        }
+
+        let index = |addr: u64| blocks.binary_search_by_key(&addr, |block| block.address).ok();
+        let successors: Vec<Vec<usize>> = exits
+            .iter()
+            .map(|insn| {
+                let mut successors = match insn.flow {
+                    // branches leaving the function are tail calls
+                    FlowControl::UnconditionalBranch => insn.target.and_then(index).into_iter().collect(),
+                    FlowControl::ConditionalBranch => index(insn.next)
+                        .into_iter()
+                        .chain(insn.target.and_then(index))
+                        .collect(),
+                    FlowControl::IndirectBranch => self
+                        .cases
+                        .get(&insn.address)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|&case| index(case))
+                        .collect(),
+                    FlowControl::Return | FlowControl::Exception => vec![],
+                    _ => index(insn.next).into_iter().collect(),
+                };
+                successors.sort_unstable();
+                successors.dedup();
+                successors
+            })
+            .collect();
+        let entry = index(entry).unwrap_or(0);
+        for (block, successors) in blocks.iter_mut().zip(successors) {
+            block.successors = successors;
+        }
+
+        find_loops(&mut blocks, entry);
+        ControlFlowGraph { blocks }
+    }
+}
+
+#[derive(Debug)]
+struct DecodedInstruction {
+    address: u64,
+    next: u64,
+    mnemonic: Mnemonic,
+    flow: FlowControl,
+    target: Option<u64>,
+}
+
+/// Marks the targets of back edges found with a depth-first search as loop headers and
+/// computes the loop depth of each block from the natural loops of the back edges.
+fn find_loops(blocks: &mut [BasicBlock], entry: usize) {
+    const UNVISITED: u8 = 0;
+    const ON_STACK: u8 = 1;
+    const DONE: u8 = 2;
+
+    let mut state = vec![UNVISITED; blocks.len()];
+    let mut back_edges = vec![];
+    // the search starts at the entry, blocks that can't be reached from it are visited after
+    for root in std::iter::once(entry).chain(0..blocks.len()) {
+        if state.get(root) != Some(&UNVISITED) {
+            continue;
+        }
+        let mut stack = vec![(root, 0)];
+        state[root] = ON_STACK;
+        while let Some((node, i)) = stack.last_mut() {
+            let node = *node;
+            let Some(&succ) = blocks[node].successors.get(*i) else {
+                state[node] = DONE;
+                stack.pop();
+                continue;
+            };
+            *i += 1;
+            match state[succ] {
+                UNVISITED => {
+                    state[succ] = ON_STACK;
+                    stack.push((succ, 0));
+                }
+                ON_STACK => back_edges.push((node, succ)),
+                _ => {}
+            }
+        }
+    }
+
+    let mut predecessors = vec![vec![]; blocks.len()];
+    for (i, block) in blocks.iter().enumerate() {
+        for &succ in &block.successors {
+            predecessors[succ].push(i);
+        }
+    }
+
+    let mut loops: HashMap<usize, HashSet<usize>> = HashMap::new();
+    for (tail, header) in back_edges {
+        let body = loops.entry(header).or_insert_with(|| HashSet::from_iter([header]));
+        let mut work = vec![tail];
+        while let Some(node) = work.pop() {
+            if body.insert(node) {
+                work.extend(&predecessors[node]);
+            }
+        }
+    }
+    for (header, body) in loops {
+        blocks[header].loop_header = true;
+        for node in body {
+            blocks[node].loop_depth += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use iced_x86::{Decoder, DecoderOptions};
+    use test_case::test_case;
+
+    use super::*;
+
+    fn build(code: &[u8]) -> ControlFlowGraph {
+        let mut builder = CfgBuilder::default();
+        for instruction in Decoder::with_ip(64, code, 0x1000, DecoderOptions::NONE) {
+            builder.push(&instruction);
+        }
+        builder.build(0x1000)
+    }
+
+    #[test]
+    fn test_build_loop() {
+        let cfg = build(&[
+            0x31, 0xC0, // xor eax, eax
+            0x83, 0xC0, 0x01, // add eax, 1
+            0x83, 0xF8, 0x0A, // cmp eax, 10
+            0x7C, 0xF8, // jl 0x1002
+            0xC3, // ret
+        ]);
+        let blocks: Vec<_> = cfg
+            .blocks()
+            .iter()
+            .map(|block| (block.address(), block.end(), block.successors(), block.loop_depth()))
+            .collect();
+        assert_eq!(
+            blocks,
+            &[
+                (0x1000, 0x1002, &[1][..], 0),
+                (0x1002, 0x100A, &[1, 2][..], 1),
+                (0x100A, 0x100B, &[][..], 0),
+            ]
+        );
+        assert!(cfg.block_at(0x1002).unwrap().is_loop_header());
+        assert_eq!(
+            cfg.block_at(0x1002).unwrap().opcodes(),
+            &[Mnemonic::Add, Mnemonic::Cmp, Mnemonic::Jl]
+        );
+    }
+
+    // if-else
+    #[test_case(
+        &[0x85, 0xFF, 0x74, 0x03, 0x31, 0xC0, 0xC3, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3],
+        Shape { blocks: 3, edges: 2, loops: 0, loop_depth: 0 }
+    )]
+    // nested loops
+    #[test_case(
+        &[0x31, 0xC9, 0x31, 0xD2, 0xFF, 0xC2, 0x39, 0xFA, 0x7C, 0xFA, 0xFF, 0xC1, 0x39, 0xF9, 0x7C, 0xF2, 0xC3],
+        Shape { blocks: 5, edges: 6, loops: 2, loop_depth: 2 }
+    )]
+    // tail jump
+    #[test_case(&[0x31, 0xC0, 0xE9, 0x00, 0x01, 0x00, 0x00], Shape { blocks: 1, edges: 0, loops: 0, loop_depth: 0 })]
+    fn test_shape(code: &[u8], expected: Shape) {
+        assert_eq!(build(code).shape(), expected);
+    }
+
+    #[test]
+    fn test_distance() {
+        // if-else
+        let lhs = build(&[
+            0x85, 0xFF, 0x74, 0x03, 0x31, 0xC0, 0xC3, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3,
+        ]);
+        // loop
+        let rhs = build(&[0x31, 0xC0, 0x83, 0xC0, 0x01, 0x83, 0xF8, 0x0A, 0x7C, 0xF8, 0xC3]);
+        assert_eq!(lhs.distance(&rhs), 3);
+        assert_eq!(lhs.normalized_distance(&rhs), 0.375);
+        assert_eq!(lhs.normalized_distance(&lhs), 0.);
+    }
+}
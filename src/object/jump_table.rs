@@ -0,0 +1,247 @@
+use hashbrown::HashMap;
+use iced_x86::{Instruction, Mnemonic, OpKind, Register, RflagsBits};
+
+use super::ObjectCode;
+
+/// The maximum number of entries read from a jump table.
+const MAX_ENTRIES: u64 = 1024;
+
+/// Tracks the instructions leading up to an indirect jump in order to recover the targets of jump
+/// tables generated for switch statements. The recognized sequences are:
+/// - `lea base, [rip+table]`, `movsxd reg, [base+idx*4]`, `add reg, base`, `jmp reg`
+///   with entries relative to the table (GCC and Clang)
+/// - `lea base, [rip+image_base]`, `mov reg, [base+idx*4+table]`, `add reg, base`, `jmp reg`
+///   with entries relative to the image base (MSVC)
+/// - `jmp [table+idx*8]` or `mov reg, [table+idx*8]`, `jmp reg` with absolute entries
+///
+/// The number of entries is taken from a preceding `cmp idx, imm` bounds check when there is one.
+/// The check is forgotten once another comparison is made or an indirect jump uses it.
+#[derive(Debug, Default)]
+pub(super) struct JumpTableTracker {
+    values: HashMap<Register, Value>,
+    cmp_imm: Option<u64>,
+    bound: Option<u64>,
+}
+
+impl JumpTableTracker {
+    /// Updates the tracked state with the effects of an instruction.
+    pub fn update(&mut self, instruction: &Instruction) {
+        // the compared value is only meaningful to the branch that directly follows the comparison
+        if instruction.rflags_modified() != RflagsBits::NONE {
+            self.cmp_imm = None;
+        }
+        match instruction.mnemonic() {
+            Mnemonic::Lea if instruction.is_ip_rel_memory_operand() => {
+                let value = Value::Address(instruction.ip_rel_memory_address());
+                self.values.insert(instruction.op0_register().full_register(), value);
+            }
+            Mnemonic::Mov | Mnemonic::Movsxd | Mnemonic::Movzx if instruction.op1_kind() == OpKind::Memory => {
+                let value = self.table_address(instruction).map(|table| Value::Entry {
+                    table,
+                    size: instruction.memory_size().size() as u64,
+                    signed: instruction.mnemonic() == Mnemonic::Movsxd,
+                });
+                self.set(instruction.op0_register(), value);
+            }
+            Mnemonic::Add if instruction.op1_kind() == OpKind::Register => {
+                let dst = instruction.op0_register().full_register();
+                let src = instruction.op1_register().full_register();
+                let value = match (self.values.get(&dst), self.values.get(&src)) {
+                    (Some(&Value::Entry { table, size, signed }), Some(&Value::Address(base)))
+                    | (Some(&Value::Address(base)), Some(&Value::Entry { table, size, signed })) => {
+                        Some(Value::Target {
+                            table,
+                            size,
+                            signed,
+                            base,
+                        })
+                    }
+                    _ => None,
+                };
+                self.set(dst, value);
+            }
+            Mnemonic::Cmp
+                if matches!(
+                    instruction.op1_kind(),
+                    OpKind::Immediate8
+                        | OpKind::Immediate8to32
+                        | OpKind::Immediate8to64
+                        | OpKind::Immediate32
+                        | OpKind::Immediate32to64
+                ) =>
+            {
+                self.cmp_imm = Some(instruction.immediate(1));
+                self.bound = None;
+            }
+            Mnemonic::Ja | Mnemonic::Jbe => self.bound = self.cmp_imm.and_then(|imm| imm.checked_add(1)),
+            Mnemonic::Jae | Mnemonic::Jb => self.bound = self.cmp_imm,
+            Mnemonic::Cmp | Mnemonic::Test => self.bound = None,
+            Mnemonic::Jmp if matches!(instruction.op0_kind(), OpKind::Register | OpKind::Memory) => {
+                self.cmp_imm = None;
+                self.bound = None;
+            }
+            Mnemonic::Push => {}
+            _ if instruction.op_count() > 0 && instruction.op0_kind() == OpKind::Register => {
+                self.set(instruction.op0_register(), None);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the targets of an indirect jump if it dispatches through a recognized jump table.
+    pub fn targets(&self, instruction: &Instruction, obj: &ObjectCode<'_>) -> Vec<u64> {
+        let ptr_size = u64::from(obj.bitness() / 8);
+        let (table, size, signed, base) = match instruction.op0_kind() {
+            OpKind::Register => match self.values.get(&instruction.op0_register().full_register()) {
+                Some(&Value::Target {
+                    table,
+                    size,
+                    signed,
+                    base,
+                }) => (table, size, signed, base),
+                Some(&Value::Entry { table, size, .. }) if size == ptr_size => (table, size, false, 0),
+                _ => return vec![],
+            },
+            OpKind::Memory if u64::from(instruction.memory_index_scale()) == ptr_size => {
+                match self.table_address(instruction) {
+                    Some(table) => (table, ptr_size, false, 0),
+                    None => return vec![],
+                }
+            }
+            _ => return vec![],
+        };
+
+        let Some(data) = obj.data_at(table) else {
+            return vec![];
+        };
+        let mut targets = vec![];
+        for entry in data
+            .chunks_exact(size as usize)
+            .take(self.bound.unwrap_or(MAX_ENTRIES).min(MAX_ENTRIES) as usize)
+        {
+            let offset = match (entry, signed) {
+                (&[a, b, c, d], true) => i32::from_le_bytes([a, b, c, d]) as u64,
+                (&[a, b, c, d], false) => u64::from(u32::from_le_bytes([a, b, c, d])),
+                (&[a, b, c, d, e, f, g, h], _) => u64::from_le_bytes([a, b, c, d, e, f, g, h]),
+                _ => break,
+            };
+            let target = base.wrapping_add(offset);
+            // without a bounds check the table ends at the first entry that doesn't point at code
+            if !obj.is_code(target) {
+                if self.bound.is_some() {
+                    return vec![];
+                }
+                break;
+            }
+            targets.push(target);
+        }
+        targets
+    }
+
+    /// Returns the address of the table accessed by a scaled index memory operand.
+    fn table_address(&self, instruction: &Instruction) -> Option<u64> {
+        if instruction.memory_index() == Register::None || instruction.memory_index_scale() < 4 {
+            return None;
+        }
+        let base = match instruction.memory_base() {
+            Register::None => 0,
+            reg => match self.values.get(&reg.full_register())? {
+                &Value::Address(addr) => addr,
+                _ => return None,
+            },
+        };
+        Some(base.wrapping_add(instruction.memory_displacement64()))
+    }
+
+    fn set(&mut self, reg: Register, value: Option<Value>) {
+        let reg = reg.full_register();
+        match value {
+            Some(value) => self.values.insert(reg, value),
+            None => self.values.remove(&reg),
+        };
+    }
+}
+
+/// A value known to be held by a register.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    /// An address loaded with a `lea` instruction.
+    Address(u64),
+    /// An entry loaded from a table.
+    Entry { table: u64, size: u64, signed: bool },
+    /// An entry loaded from a table added to a base address.
+    Target {
+        table: u64,
+        size: u64,
+        signed: bool,
+        base: u64,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use iced_x86::{Decoder, DecoderOptions};
+    use test_case::test_case;
+
+    use super::*;
+    use crate::object::test::test_object;
+    use crate::object::MappedSection;
+
+    fn cases(code: &[u8], table: &[u8]) -> Vec<u64> {
+        let text = [0xC3; 0x200];
+        let obj = test_object(vec![
+            MappedSection::new(0x1000, &text, true, false),
+            MappedSection::new(0x3000, table, false, true),
+        ]);
+        let mut tracker = JumpTableTracker::default();
+        let mut cases = vec![];
+        for instruction in Decoder::with_ip(64, code, 0x1000, DecoderOptions::NONE) {
+            if instruction.mnemonic() == Mnemonic::Jmp && instruction.op0_kind() != OpKind::NearBranch64 {
+                cases = tracker.targets(&instruction, &obj);
+            }
+            tracker.update(&instruction);
+        }
+        cases
+    }
+
+    fn table(entries: &[u64], size: usize) -> Vec<u8> {
+        entries
+            .iter()
+            .flat_map(|entry| entry.to_le_bytes()[..size].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_image_base_table() {
+        let code = [
+            0x83, 0xF9, 0x01, // cmp ecx, 1
+            0x77, 0x12, // ja 0x1019
+            0x48, 0x8D, 0x15, 0xF4, 0xEF, 0xFF, 0xFF, // lea rdx, [rip-0x100C]
+            0x8B, 0x84, 0x8A, 0x00, 0x30, 0x00, 0x00, // mov eax, dword ptr [rdx+rcx*4+0x3000]
+            0x48, 0x01, 0xD0, // add rax, rdx
+            0xFF, 0xE0, // jmp rax
+        ];
+        assert_eq!(cases(&code, &table(&[0x1100, 0x1110, 0x1120], 4)), &[0x1100, 0x1110]);
+    }
+
+    #[test_case(&[], &[0x1100, 0x1110, 0x1120]; "unbounded")]
+    #[test_case(&[0x83, 0xF9, 0x01, 0x77, 0x07], &[0x1100, 0x1110]; "bounded")]
+    #[test_case(&[0x83, 0xF9, 0x01, 0x72, 0x07], &[0x1100]; "bounded below")]
+    #[test_case(&[0x83, 0xF9, 0x01, 0x39, 0xC1, 0x77, 0x07], &[0x1100, 0x1110, 0x1120]; "compared again")]
+    #[test_case(&[0x83, 0xF9, 0x01, 0x77, 0x07, 0xFF, 0xE0], &[0x1100, 0x1110, 0x1120]; "used by a previous jump")]
+    fn test_absolute_table(check: &[u8], expected: &[u64]) {
+        // jmp qword ptr [rcx*8+0x3000]
+        let code = [check, &[0xFF, 0x24, 0xCD, 0x00, 0x30, 0x00, 0x00]].concat();
+        assert_eq!(cases(&code, &table(&[0x1100, 0x1110, 0x1120, 0], 8)), expected);
+    }
+
+    #[test]
+    fn test_bound_overflow() {
+        let mut tracker = JumpTableTracker::default();
+        // cmp rax, -1; ja 0x1006
+        for instruction in Decoder::with_ip(64, &[0x48, 0x83, 0xF8, 0xFF, 0x77, 0x00], 0x1000, DecoderOptions::NONE) {
+            tracker.update(&instruction);
+        }
+        assert_eq!(tracker.bound, None);
+    }
+}
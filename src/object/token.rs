@@ -0,0 +1,168 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use iced_x86::{Instruction, Mnemonic, OpKind, Register};
+
+/// The level of detail at which instructions of functions are compared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// Instructions are compared by their mnemonics only.
+    #[default]
+    Mnemonic,
+    /// Instructions are compared by their mnemonics and the kinds of their operands, with
+    /// registers abstracted to their classes.
+    Operands,
+}
+
+impl FromStr for Granularity {
+    type Err = ParseGranularityError;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "mnemonic" => Ok(Granularity::Mnemonic),
+            "operands" => Ok(Granularity::Operands),
+            _ => Err(ParseGranularityError(str.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGranularityError(String);
+
+impl fmt::Display for ParseGranularityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown granularity: {}, expected mnemonic or operands", self.0)
+    }
+}
+
+impl StdError for ParseGranularityError {}
+
+/// A normalized instruction made of its mnemonic and the kinds of its operands. Tokens don't
+/// depend on the exact registers, immediates and addresses, which change between compilations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token {
+    mnemonic: Mnemonic,
+    operands: [Operand; 4],
+}
+
+impl Token {
+    /// Creates a token from a decoded instruction.
+    pub fn new(instruction: &Instruction) -> Self {
+        let mut operands = [Operand::None; 4];
+        for (i, operand) in (0..instruction.op_count()).zip(&mut operands) {
+            *operand = match instruction.op_kind(i) {
+                OpKind::Register => Operand::Register(RegisterClass::of(instruction.op_register(i))),
+                OpKind::NearBranch16
+                | OpKind::NearBranch32
+                | OpKind::NearBranch64
+                | OpKind::FarBranch16
+                | OpKind::FarBranch32 => Operand::Branch,
+                OpKind::Immediate8
+                | OpKind::Immediate8_2nd
+                | OpKind::Immediate16
+                | OpKind::Immediate32
+                | OpKind::Immediate64
+                | OpKind::Immediate8to16
+                | OpKind::Immediate8to32
+                | OpKind::Immediate8to64
+                | OpKind::Immediate32to64 => Operand::Immediate,
+                _ => Operand::Memory,
+            };
+        }
+        Self {
+            mnemonic: instruction.mnemonic(),
+            operands,
+        }
+    }
+
+    /// Creates a token of an instruction without operands.
+    #[cfg(test)]
+    pub(crate) fn from_mnemonic(mnemonic: Mnemonic) -> Self {
+        Self {
+            mnemonic,
+            operands: [Operand::None; 4],
+        }
+    }
+
+    /// Returns the mnemonic of the instruction.
+    #[inline]
+    pub fn mnemonic(&self) -> Mnemonic {
+        self.mnemonic
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    None,
+    Register(RegisterClass),
+    Memory,
+    Immediate,
+    Branch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RegisterClass {
+    Gpr8,
+    Gpr16,
+    Gpr32,
+    Gpr64,
+    Vector,
+    Float,
+    Mask,
+    Segment,
+    Other,
+}
+
+impl RegisterClass {
+    fn of(register: Register) -> Self {
+        if register.is_gpr8() {
+            RegisterClass::Gpr8
+        } else if register.is_gpr16() {
+            RegisterClass::Gpr16
+        } else if register.is_gpr32() {
+            RegisterClass::Gpr32
+        } else if register.is_gpr64() {
+            RegisterClass::Gpr64
+        } else if register.is_vector_register() {
+            RegisterClass::Vector
+        } else if register.is_st() {
+            RegisterClass::Float
+        } else if register.is_k() {
+            RegisterClass::Mask
+        } else if register.is_segment_register() {
+            RegisterClass::Segment
+        } else {
+            RegisterClass::Other
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use iced_x86::{Decoder, DecoderOptions};
+    use test_case::test_case;
+
+    use super::*;
+
+    fn token(code: &[u8]) -> Token {
+        Token::new(&Decoder::with_ip(64, code, 0x1000, DecoderOptions::NONE).decode())
+    }
+
+    #[test_case(&[0x48, 0x89, 0xD8], &[0x48, 0x89, 0xD1], true; "mov rax, rbx and mov rcx, rdx")]
+    #[test_case(&[0x48, 0x89, 0xD8], &[0x89, 0xD8], false; "mov rax, rbx and mov eax, ebx")]
+    #[test_case(&[0x48, 0x89, 0xD8], &[0x48, 0xC7, 0x44, 0x24, 0x08, 0x00, 0x00, 0x00, 0x00], false; "mov rax, rbx and mov qword ptr [rsp + 8], 0")]
+    #[test_case(&[0xB8, 0x01, 0x00, 0x00, 0x00], &[0xB8, 0x02, 0x00, 0x00, 0x00], true; "mov eax, 1 and mov eax, 2")]
+    #[test_case(&[0x8B, 0x43, 0x08], &[0x8B, 0x41, 0x10], true; "mov eax, [rbx + 8] and mov eax, [rcx + 16]")]
+    fn test_token_equality(lhs: &[u8], rhs: &[u8], expected: bool) {
+        assert_eq!(token(lhs) == token(rhs), expected);
+        assert_eq!(token(lhs).mnemonic(), token(rhs).mnemonic());
+    }
+
+    #[test]
+    fn test_parse_granularity() {
+        assert_eq!("mnemonic".parse(), Ok(Granularity::Mnemonic));
+        assert_eq!("operands".parse(), Ok(Granularity::Operands));
+        assert!("registers".parse::<Granularity>().is_err());
+    }
+}
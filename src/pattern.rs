@@ -0,0 +1,222 @@
+use std::fmt;
+use std::str::FromStr;
+
+use iced_x86::{Decoder, DecoderOptions, OpKind};
+
+use crate::object::ObjectCode;
+
+/// An IDA-style byte pattern, such as `48 8B ?? ?? E8 ? ? ? ?`, followed by operations that
+/// turn the address of a match into the address of interest.
+///
+/// The operations are separated from the bytes and from each other with commas:
+/// - `offset <n>` adds a signed decimal or hexadecimal (`0x`) number to the address
+/// - `follow` replaces the address with the target of the call or the jump at the address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+    operations: Vec<Operation>,
+}
+
+impl Pattern {
+    /// Returns the addresses the pattern resolves to in the executable sections of an object.
+    /// The addresses are sorted and deduplicated.
+    pub fn scan(&self, obj: &ObjectCode<'_>) -> Vec<u64> {
+        let mut addrs: Vec<_> = obj
+            .code_sections()
+            .flat_map(|(address, data)| self.find_in(data).map(move |offset| address + offset as u64))
+            .filter_map(|addr| self.resolve(addr, obj))
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Returns the address the pattern resolves to if it's unique in an object.
+    pub fn scan_unique(&self, obj: &ObjectCode<'_>) -> Option<u64> {
+        match self.scan(obj)[..] {
+            [addr] => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the offsets at which the pattern matches the data.
+    pub fn find_in<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let len = self.bytes.len();
+        (0..(data.len() + 1).saturating_sub(len)).filter(move |&i| {
+            self.bytes
+                .iter()
+                .zip(&data[i..i + len])
+                .all(|(pat, byte)| pat.is_none_or(|pat| pat == *byte))
+        })
+    }
+
+    fn resolve(&self, addr: u64, obj: &ObjectCode<'_>) -> Option<u64> {
+        self.operations.iter().try_fold(addr, |addr, op| match *op {
+            Operation::Offset(offset) => Some(addr.wrapping_add_signed(offset)),
+            Operation::Follow => {
+                let instruction =
+                    Decoder::with_ip(obj.bitness(), obj.code_at(addr)?, addr, DecoderOptions::NONE).decode();
+                match instruction.op0_kind() {
+                    OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+                        Some(instruction.near_branch_target())
+                    }
+                    _ => None,
+                }
+            }
+        })
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = ParseError;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let mut parts = str.split(',');
+        let bytes = parts
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|byte| match byte {
+                "?" | "??" => Ok(None),
+                _ if byte.len() == 2 => u8::from_str_radix(byte, 16)
+                    .map(Some)
+                    .map_err(|_| ParseError::InvalidByte(byte.into())),
+                _ => Err(ParseError::InvalidByte(byte.into())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if bytes.iter().all(Option::is_none) {
+            return Err(ParseError::EmptyPattern);
+        }
+        let operations = parts.map(str::parse).collect::<Result<_, _>>()?;
+        Ok(Self { bytes, operations })
+    }
+}
+
+/// An operation applied to the address of a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Offset(i64),
+    Follow,
+}
+
+impl FromStr for Operation {
+    type Err = ParseError;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidOperation(str.trim().into());
+        match str.split_whitespace().collect::<Vec<_>>()[..] {
+            ["follow"] => Ok(Operation::Follow),
+            ["offset", num] => {
+                let (negative, num) = match num.strip_prefix('-') {
+                    Some(num) => (true, num),
+                    None => (false, num.strip_prefix('+').unwrap_or(num)),
+                };
+                let num = match num.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => num.parse(),
+                }
+                .map_err(|_| invalid())?;
+                Ok(Operation::Offset(if negative { -num } else { num }))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A pattern with a name, as found in a signature file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// The name of the signature.
+    pub name: String,
+    /// The pattern of the signature.
+    pub pattern: Pattern,
+}
+
+/// Parses a signature file. Each line holds a name and a pattern separated by `=`, empty lines
+/// and lines starting with `#` are skipped.
+pub fn parse_signatures(text: &str) -> Result<Vec<Signature>, ParseError> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let (name, pattern) = line.split_once('=').ok_or(ParseError::MissingName(i + 1))?;
+            Ok(Signature {
+                name: name.trim().into(),
+                pattern: pattern.parse()?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidByte(String),
+    InvalidOperation(String),
+    EmptyPattern,
+    MissingName(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidByte(byte) => write!(f, "invalid pattern byte: {}", byte),
+            ParseError::InvalidOperation(op) => write!(f, "invalid pattern operation: {}", op),
+            ParseError::EmptyPattern => write!(f, "pattern has no known bytes"),
+            ParseError::MissingName(line) => write!(f, "missing signature name on line {}", line),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_pattern() {
+        let pattern: Pattern = "48 8B ?? ?? E8 ? ? ? ?, offset 4, follow".parse().unwrap();
+        assert_eq!(
+            pattern.bytes,
+            &[Some(0x48), Some(0x8B), None, None, Some(0xE8), None, None, None, None]
+        );
+        assert_eq!(pattern.operations, &[Operation::Offset(4), Operation::Follow]);
+    }
+
+    #[test_case("48 8B, offset -0x10", Ok(Operation::Offset(-0x10)))]
+    #[test_case("48 8B, offset +12", Ok(Operation::Offset(12)))]
+    #[test_case("48 8B, offset", Err(ParseError::InvalidOperation("offset".into())))]
+    #[test_case("48 8B, deref", Err(ParseError::InvalidOperation("deref".into())))]
+    #[test_case("48 8G", Err(ParseError::InvalidByte("8G".into())))]
+    #[test_case("48 8BE", Err(ParseError::InvalidByte("8BE".into())))]
+    #[test_case("?? ?", Err(ParseError::EmptyPattern))]
+    fn test_parse_operation(str: &str, expected: Result<Operation, ParseError>) {
+        let res = str.parse::<Pattern>().map(|pattern| pattern.operations[0]);
+        assert_eq!(res, expected);
+    }
+
+    #[test_case("E8 ? ? ? ? C3", &[1, 7])]
+    #[test_case("C3", &[6, 12])]
+    #[test_case("? C3", &[5, 11])]
+    #[test_case("90 90", &[])]
+    fn test_find_in(pattern: &str, expected: &[usize]) {
+        let data = [
+            0x90, 0xE8, 0x00, 0x00, 0x00, 0x00, 0xC3, 0xE8, 0x01, 0x02, 0x03, 0x04, 0xC3,
+        ];
+        let pattern: Pattern = pattern.parse().unwrap();
+        assert_eq!(pattern.find_in(&data).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_parse_signatures() {
+        let text = "# players\nupdate_player = 48 8B ?? E8, offset 3, follow\n\nspawn = C3\n";
+        let signatures = parse_signatures(text).unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures[0].name, "update_player");
+        assert_eq!(signatures[1].pattern, "C3".parse().unwrap());
+        assert_eq!(parse_signatures("C3"), Err(ParseError::MissingName(1)));
+    }
+}
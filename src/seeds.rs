@@ -0,0 +1,270 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use hashbrown::hash_map::Entry;
+use hashbrown::HashMap;
+
+use crate::object::{CodeMetadata, ObjectCode};
+use crate::pattern::Signature;
+
+/// Pairs up functions whose symbols have the same name in both object files.
+/// Names that refer to more than one function in either file are ignored. Functions with
+/// several names in common are only paired once.
+pub fn symbols(lhs: &ObjectCode<'_>, rhs: &ObjectCode<'_>) -> Vec<(u64, u64)> {
+    let mut seeds = pair_by_key(
+        lhs.symbols().iter().map(|sym| (sym.name, sym.address)),
+        rhs.symbols().iter().map(|sym| (sym.name, sym.address)),
+    );
+    seeds.dedup();
+    seeds
+}
+
+/// Pairs up the import slots of functions imported under the same name in both object files.
+/// Names imported through more than one slot in either file are ignored.
+pub fn imports(lhs: &ObjectCode<'_>, rhs: &ObjectCode<'_>) -> Vec<(u64, u64)> {
+    pair_by_key(
+        lhs.imports().map(|(slot, name)| (name, slot)),
+        rhs.imports().map(|(slot, name)| (name, slot)),
+    )
+}
+
+/// Pairs up functions whose code hashes to the same value in both files. The hashes cover
+/// the instructions along with their operands, apart from the branch targets and relative
+/// addresses, or only the opcodes when the operands are masked. Hashes shared by more than
+/// one function in either file are ignored.
+pub fn hashes(lhs: &CodeMetadata, rhs: &CodeMetadata, mask_operands: bool) -> Vec<(u64, u64)> {
+    let hashes = |metadata: &CodeMetadata| -> Vec<_> {
+        metadata
+            .functions()
+            .filter_map(|addr| {
+                let func = metadata.get_function(addr)?;
+                if func.opcodes().is_empty() {
+                    return None;
+                }
+                let hash = if mask_operands {
+                    let mut hasher = DefaultHasher::new();
+                    func.opcodes().hash(&mut hasher);
+                    hasher.finish()
+                } else {
+                    func.instruction_hash()
+                };
+                Some((hash, addr))
+            })
+            .collect()
+    };
+    pair_by_key(hashes(lhs), hashes(rhs))
+}
+
+/// Pairs up functions that reference the same string in both files. Strings referenced by
+/// more than one function in either file are ignored, and so are functions paired with more
+/// than one function through different strings.
+pub fn strings(lhs: &CodeMetadata, rhs: &CodeMetadata) -> Vec<(u64, u64)> {
+    let mut pairs = pair_by_key(string_refs(lhs), string_refs(rhs));
+    pairs.dedup();
+
+    let mut counts = HashMap::new();
+    for &(l, r) in &pairs {
+        *counts.entry((l, true)).or_insert(0) += 1;
+        *counts.entry((r, false)).or_insert(0) += 1;
+    }
+    pairs.retain(|&(l, r)| counts[&(l, true)] == 1 && counts[&(r, false)] == 1);
+    pairs
+}
+
+/// Pairs up the addresses that the patterns of the signatures resolve to in both files.
+/// Patterns that don't resolve to a single address in either file are ignored.
+pub fn patterns(lhs: &ObjectCode<'_>, rhs: &ObjectCode<'_>, signatures: &[Signature]) -> Vec<(u64, u64)> {
+    let mut seeds: Vec<_> = signatures
+        .iter()
+        .filter_map(|sig| Some((sig.pattern.scan_unique(lhs)?, sig.pattern.scan_unique(rhs)?)))
+        .collect();
+    seeds.sort_unstable();
+    seeds.dedup();
+    seeds
+}
+
+fn string_refs(metadata: &CodeMetadata) -> impl Iterator<Item = (&str, u64)> {
+    metadata.functions().flat_map(move |addr| {
+        let func = metadata.get_function(addr).unwrap();
+        func.strings().iter().map(move |string| (string.as_ref(), addr))
+    })
+}
+
+fn pair_by_key<K: Hash + Eq>(
+    lhs: impl IntoIterator<Item = (K, u64)>,
+    rhs: impl IntoIterator<Item = (K, u64)>,
+) -> Vec<(u64, u64)> {
+    let lhs_keys = unique_keys(lhs);
+    let rhs_keys = unique_keys(rhs);
+
+    let mut seeds: Vec<_> = lhs_keys
+        .iter()
+        .filter_map(|(key, &l)| Some((l?, rhs_keys.get(key).copied()??)))
+        .collect();
+    seeds.sort_unstable();
+    seeds
+}
+
+fn unique_keys<K: Hash + Eq>(it: impl IntoIterator<Item = (K, u64)>) -> HashMap<K, Option<u64>> {
+    let mut keys = HashMap::new();
+    for (key, addr) in it {
+        match keys.entry(key) {
+            Entry::Occupied(mut entry) if *entry.get() != Some(addr) => {
+                entry.insert(None);
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(entry) => {
+                entry.insert(Some(addr));
+            }
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod test {
+    use iced_x86::Mnemonic;
+
+    use super::*;
+    use crate::graph::Graph;
+    use crate::object::{FunctionMetadata, FunctionSymbol, LoadOptions};
+
+    #[test]
+    fn test_pair_by_key() {
+        let lhs = [("a", 1), ("b", 2), ("c", 3), ("c", 4), ("d", 5), ("d", 5)];
+        let rhs = [("a", 10), ("c", 30), ("d", 50), ("e", 60)];
+        assert_eq!(pair_by_key(lhs, rhs), &[(1, 10), (5, 50)]);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let object = |symbols: &[(&'static str, u64)]| {
+            ObjectCode::with_symbols(
+                symbols
+                    .iter()
+                    .map(|&(name, address)| FunctionSymbol { name, address, size: 0 })
+                    .collect(),
+            )
+        };
+        let lhs = object(&[
+            ("main", 1),
+            ("_main", 1),
+            ("open", 2),
+            ("close", 3),
+            ("dup", 4),
+            ("dup", 5),
+        ]);
+        let rhs = object(&[("main", 11), ("_main", 11), ("__open", 12), ("open", 12), ("dup", 14)]);
+        assert_eq!(symbols(&lhs, &rhs), &[(1, 11), (2, 12)]);
+    }
+
+    #[test]
+    fn test_strings() {
+        let metadata = |functions: &[(u64, &[&str])]| CodeMetadata {
+            call_graph: Graph::new(),
+            functions: functions
+                .iter()
+                .map(|&(addr, strings)| {
+                    (
+                        addr,
+                        FunctionMetadata::new(vec![]).with_strings(strings.iter().copied()),
+                    )
+                })
+                .collect(),
+        };
+        let lhs = metadata(&[
+            (1, &["usage", "usage: %s"]),
+            (2, &["error"]),
+            (3, &["error", "oops"]),
+            (4, &["first", "second"]),
+        ]);
+        let rhs = metadata(&[
+            (11, &["usage: %s"]),
+            (12, &["error"]),
+            (13, &["oops"]),
+            (14, &["first"]),
+            (15, &["second"]),
+        ]);
+        assert_eq!(strings(&lhs, &rhs), &[(1, 11), (3, 13)]);
+    }
+
+    #[test]
+    fn test_masked_hashes() {
+        let metadata = |functions: &[(u64, &[Mnemonic])]| CodeMetadata::with_calls(0, &[], functions);
+        let lhs = metadata(&[
+            (1, &[Mnemonic::Mov, Mnemonic::Ret]),
+            (2, &[Mnemonic::Push, Mnemonic::Ret]),
+            (3, &[Mnemonic::Push, Mnemonic::Ret]),
+            (4, &[]),
+        ]);
+        let rhs = metadata(&[
+            (11, &[Mnemonic::Mov, Mnemonic::Ret]),
+            (12, &[Mnemonic::Push, Mnemonic::Ret]),
+            (14, &[]),
+        ]);
+        assert_eq!(hashes(&lhs, &rhs, true), &[(1, 11)]);
+    }
+
+    #[test]
+    fn test_unreachable_hashes() {
+        let mut lhs = vec![0xCC; 0x100];
+        // xor eax, eax, ret
+        lhs[..0x03].copy_from_slice(&[0x31, 0xC0, 0xC3]);
+        // call 0x1030, ret
+        lhs[0x10..0x16].copy_from_slice(&[0xE8, 0x1B, 0x00, 0x00, 0x00, 0xC3]);
+        // mov eax, 1, ret
+        lhs[0x30..0x36].copy_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+        let mut rhs = vec![0xCC; 0x100];
+        // xor eax, eax, ret
+        rhs[..0x03].copy_from_slice(&[0x31, 0xC0, 0xC3]);
+        // nop, call 0x1040, ret
+        rhs[0x10..0x17].copy_from_slice(&[0x90, 0xE8, 0x2A, 0x00, 0x00, 0x00, 0xC3]);
+        // mov eax, 1, ret
+        rhs[0x40..0x46].copy_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+        let (lhs, rhs) = (
+            ObjectCode::with_sections(&lhs, &[]),
+            ObjectCode::with_sections(&rhs, &[]),
+        );
+
+        let load = |obj, options| CodeMetadata::load_with_options(obj, [], options).unwrap();
+        let options = LoadOptions::default();
+        assert_eq!(
+            hashes(&load(&lhs, &options), &load(&rhs, &options), false),
+            &[(0x1000, 0x1000)]
+        );
+        let options = LoadOptions {
+            function_starts: true,
+            ..LoadOptions::default()
+        };
+        assert_eq!(
+            hashes(&load(&lhs, &options), &load(&rhs, &options), false),
+            &[(0x1000, 0x1000), (0x1030, 0x1040)]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_strings() {
+        let data = b"usage: %s\0";
+        let mut lhs = vec![0xCC; 0x100];
+        // call 0x1030, ret
+        lhs[0x10..0x16].copy_from_slice(&[0xE8, 0x1B, 0x00, 0x00, 0x00, 0xC3]);
+        // lea rax, [rip+0xFC9], ret
+        lhs[0x30..0x38].copy_from_slice(&[0x48, 0x8D, 0x05, 0xC9, 0x0F, 0x00, 0x00, 0xC3]);
+        let mut rhs = vec![0xCC; 0x100];
+        // call 0x1040, ret
+        rhs[0x10..0x16].copy_from_slice(&[0xE8, 0x2B, 0x00, 0x00, 0x00, 0xC3]);
+        // lea rax, [rip+0xFB9], ret
+        rhs[0x40..0x48].copy_from_slice(&[0x48, 0x8D, 0x05, 0xB9, 0x0F, 0x00, 0x00, 0xC3]);
+        let (lhs, rhs) = (
+            ObjectCode::with_sections(&lhs, data),
+            ObjectCode::with_sections(&rhs, data),
+        );
+
+        let options = LoadOptions {
+            function_starts: true,
+            ..LoadOptions::default()
+        };
+        let load = |obj| CodeMetadata::load_with_options(obj, [], &options).unwrap();
+        assert_eq!(strings(&load(&lhs), &load(&rhs)), &[(0x1030, 0x1040)]);
+    }
+}